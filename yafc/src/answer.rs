@@ -0,0 +1,50 @@
+use crate::{ast::YafcExpr, simplifier::Simplifier};
+use lalrpop_util::{lexer::Token, ParseError};
+
+//
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnswerVerdict<'a> {
+    Correct,
+    Incorrect,
+    Unparseable(ParseError<usize, Token<'a>, String>),
+}
+
+/// Check if the `student`s answer is mathematically the same as the `expected` one
+pub fn check_answer<'a>(expected: &'a str, student: &'a str) -> AnswerVerdict<'a> {
+    let expected = match YafcExpr::parse_infix(expected) {
+        Ok(expr) => expr,
+        Err(err) => return AnswerVerdict::Unparseable(err),
+    };
+    let student = match YafcExpr::parse_infix(student) {
+        Ok(expr) => expr,
+        Err(err) => return AnswerVerdict::Unparseable(err),
+    };
+
+    if Simplifier::equivalent(&expected, &student) {
+        AnswerVerdict::Correct
+    } else {
+        AnswerVerdict::Incorrect
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::{check_answer, AnswerVerdict};
+
+    #[test]
+    fn equivalent_answers() {
+        assert_eq!(check_answer("2*x", "x + x"), AnswerVerdict::Correct);
+        assert_eq!(
+            check_answer("(x + 1)^2", "(x + 1)*(x + 1)"),
+            AnswerVerdict::Correct
+        );
+        assert_eq!(check_answer("2*x", "x + 1"), AnswerVerdict::Incorrect);
+        assert!(matches!(
+            check_answer("2*x", "x +"),
+            AnswerVerdict::Unparseable(_)
+        ));
+    }
+}
@@ -1,7 +1,8 @@
-use egg::{define_language, Id, RecExpr, Symbol};
+use egg::{define_language, Id, Language, RecExpr, Symbol};
 use lalrpop_util::{lalrpop_mod, lexer::Token, ParseError};
 use std::{
-    fmt,
+    collections::HashMap,
+    fmt::{self, Write},
     ops::{Deref, DerefMut},
 };
 
@@ -14,21 +15,109 @@ lalrpop_mod!(
 
 //
 
-pub type Num = i64; // todo ratio + bignum
+pub type Num = i64; // todo bignum
+
+/// Exact value of a constant subexpression, `7/2` is stored as `7 * 2^-1`
+pub type Rational = num_rational::Ratio<Num>;
+
+/// Decimal literal like `0.5`, compared and hashed by its bits so that it can be an e-node
+#[derive(Debug, Clone, Copy)]
+pub struct Float(pub f64);
+
+impl Float {
+    /// The exact value of the decimal digits, `0.1` is `1/10`
+    ///
+    /// `None` if it doesn't fit a [`Rational`]
+    pub fn to_rational(self) -> Option<Rational> {
+        let s = self.0.to_string();
+        let (whole, fract) = s.split_once('.').unwrap_or((&s, ""));
+        let denom = (10 as Num).checked_pow(fract.len().try_into().ok()?)?;
+        let numer: Num = format!("{whole}{fract}").parse().ok()?;
+        Some(Rational::new(numer, denom))
+    }
+}
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl std::hash::Hash for Float {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl std::str::FromStr for Float {
+    type Err = &'static str;
+
+    /// Only with a decimal point, so that integers are still read as [`YafcLanguage::Num`]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.contains('.') {
+            return Err("not a decimal literal");
+        }
+        s.parse().map(Float).map_err(|_| "not a decimal literal")
+    }
+}
+
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.fract() == 0.0 && self.0.is_finite() {
+            write!(f, "{:.1}", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BinOp {
     Add,
     Mul,
     Pow,
+    Max,
+    Min,
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    /// base, argument
+    Log,
 }
 
 impl BinOp {
-    fn precedence(self) -> u8 {
+    /// Binding strength used when printing, lower binds tighter
+    ///
+    /// `^` is 2, `*` is 3, `+` is 4, relations are 5, `and` is 6 and `or` is 7.
+    /// Operators printed as function calls are 0
+    pub fn precedence(self) -> u8 {
         match self {
+            BinOp::Or => 7,
+            BinOp::And => 6,
+            BinOp::Eq | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 5,
             BinOp::Add => 4,
             BinOp::Mul => 3,
             BinOp::Pow => 2,
+            // function call syntax, never needs parentheses
+            BinOp::Max | BinOp::Min | BinOp::Log => 0,
         }
     }
 }
@@ -36,12 +125,24 @@ impl BinOp {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum UnOp {
     Fac,
+    DoubleFac,
+    Floor,
+    Ceil,
+    Sin,
+    Cos,
+    Tan,
+    Abs,
 }
 
 impl UnOp {
-    fn precedence(self) -> u8 {
+    /// Binding strength used when printing, on the same scale as [`BinOp::precedence`]
+    ///
+    /// `!` and `!!` are 1, operators printed as function calls are 0
+    pub fn precedence(self) -> u8 {
         match self {
-            UnOp::Fac => 1,
+            UnOp::Fac | UnOp::DoubleFac => 1,
+            // function call syntax, never needs parentheses
+            UnOp::Floor | UnOp::Ceil | UnOp::Sin | UnOp::Cos | UnOp::Tan | UnOp::Abs => 0,
         }
     }
 }
@@ -49,16 +150,60 @@ impl UnOp {
 define_language! {
     pub enum YafcLanguage {
         Num(Num),
+        Float(Float),
+        // the imaginary unit, `i^2 = -1`, before `Var` so that the rewrites read `i` as it
+        "i" = Imag,
+        // Euler's number, `ln(x)` is `log(e, x)`
+        "e" = Euler,
         Var(Symbol),
+        // an arbitrary constant like the `C` of an integral, never parsed
+        Const(Symbol),
 
         "+" = Add([Id; 2]),
         "*" = Mul([Id; 2]),
         "^" = Pow([Id; 2]),
+        "max" = Max([Id; 2]),
+        "min" = Min([Id; 2]),
+        // base, argument
+        "log" = Log([Id; 2]),
+
+        "=" = Eq([Id; 2]),
+        "<" = Lt([Id; 2]),
+        "<=" = Le([Id; 2]),
+        ">" = Gt([Id; 2]),
+        ">=" = Ge([Id; 2]),
+        "and" = And([Id; 2]),
+        "or" = Or([Id; 2]),
 
         "!" = Fac(Id),
+        "!!" = DoubleFac(Id),
+        "floor" = Floor(Id),
+        "ceil" = Ceil(Id),
+        "sin" = Sin(Id),
+        "cos" = Cos(Id),
+        "tan" = Tan(Id),
+        "abs" = Abs(Id),
+
+        // left alone by the simplifier
+        "opaque" = Opaque(Id),
+
+        // index, from, to, body
+        "sum" = Sum([Id; 4]),
+
+        // calls to user defined functions, `f(x, y)`
+        Call(Symbol, Vec<Id>),
+
+        // condition, value, condition, value, ..: the first branch that holds is the value
+        "piecewise" = Piecewise(Vec<Id>),
+
+        // `[[1, 2], [3, 4]]`, the rows all have the same length
+        "matrix" = Matrix(Vec<Id>),
+        "row" = Row(Vec<Id>),
     }
 }
 
+/// `==` is structural: `x + y` and `y + x` are different trees,
+/// [`YafcExpr::math_eq`] compares them mathematically
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct YafcExpr {
     pub(crate) expr: RecExpr<YafcLanguage>,
@@ -69,6 +214,11 @@ pub struct YafcExpr {
 pub struct Pretty<'a> {
     expr: &'a YafcExpr,
     style: PrettyStyle,
+    radix: Radix,
+    raw: bool,
+    factorial: FactorialStyle,
+    fraction: FractionStyle,
+    spacing: Option<Spacing>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -82,18 +232,80 @@ pub enum PrettyStyle {
     LaTeX,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Radix {
+    /// 255
+    #[default]
+    Dec,
+
+    /// 0xff
+    Hex,
+
+    /// 0b11111111
+    Bin,
+
+    /// 0o377
+    Oct,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FactorialStyle {
+    /// n!
+    #[default]
+    Bang,
+
+    /// Γ(n + 1)
+    Gamma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FractionStyle {
+    /// 7 / 2
+    #[default]
+    Improper,
+
+    /// 3 1/2, the way the parser reads mixed numbers
+    Mixed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spacing {
+    /// a+b*c, with a space between two minus signs: a- -b
+    Tight,
+
+    /// a + b * c
+    Loose,
+}
+
 impl YafcLanguage {
     pub fn bin(operator: BinOp, operands: [Id; 2]) -> Self {
         match operator {
             BinOp::Add => Self::Add(operands),
             BinOp::Mul => Self::Mul(operands),
             BinOp::Pow => Self::Pow(operands),
+            BinOp::Max => Self::Max(operands),
+            BinOp::Min => Self::Min(operands),
+            BinOp::Eq => Self::Eq(operands),
+            BinOp::Lt => Self::Lt(operands),
+            BinOp::Le => Self::Le(operands),
+            BinOp::Gt => Self::Gt(operands),
+            BinOp::Ge => Self::Ge(operands),
+            BinOp::And => Self::And(operands),
+            BinOp::Or => Self::Or(operands),
+            BinOp::Log => Self::Log(operands),
         }
     }
 
     pub fn un(operator: UnOp, operand: Id) -> Self {
         match operator {
             UnOp::Fac => Self::Fac(operand),
+            UnOp::DoubleFac => Self::DoubleFac(operand),
+            UnOp::Floor => Self::Floor(operand),
+            UnOp::Ceil => Self::Ceil(operand),
+            UnOp::Sin => Self::Sin(operand),
+            UnOp::Cos => Self::Cos(operand),
+            UnOp::Tan => Self::Tan(operand),
+            UnOp::Abs => Self::Abs(operand),
         }
     }
 
@@ -103,11 +315,58 @@ impl YafcLanguage {
             Add(_) => ('+', Some(BinOp::Add.precedence())),
             Mul(_) => ('*', Some(BinOp::Mul.precedence())),
             Pow(_) => ('^', Some(BinOp::Pow.precedence())),
+            Max(_) => (' ', Some(BinOp::Max.precedence())),
+            Min(_) => (' ', Some(BinOp::Min.precedence())),
+            Log(_) => (' ', Some(BinOp::Log.precedence())),
+            Eq(_) => ('=', Some(BinOp::Eq.precedence())),
+            Lt(_) => ('<', Some(BinOp::Lt.precedence())),
+            Le(_) => ('≤', Some(BinOp::Le.precedence())),
+            Gt(_) => ('>', Some(BinOp::Gt.precedence())),
+            Ge(_) => ('≥', Some(BinOp::Ge.precedence())),
+            And(_) => ('∧', Some(BinOp::And.precedence())),
+            Or(_) => ('∨', Some(BinOp::Or.precedence())),
             Fac(_) => ('!', Some(UnOp::Fac.precedence())),
+            DoubleFac(_) => ('!', Some(UnOp::DoubleFac.precedence())),
+            Floor(_) => (' ', Some(UnOp::Floor.precedence())),
+            Ceil(_) => (' ', Some(UnOp::Ceil.precedence())),
+            Sin(_) => (' ', Some(UnOp::Sin.precedence())),
+            Cos(_) => (' ', Some(UnOp::Cos.precedence())),
+            Tan(_) => (' ', Some(UnOp::Tan.precedence())),
+            Abs(_) => (' ', Some(UnOp::Abs.precedence())),
+            Opaque(_) => (' ', Some(0)),
+            Call(..) | Piecewise(_) => (' ', Some(0)),
             _ => (' ', None),
         }
     }
 
+    /// The operator and the sides if the node is a relation like `a = b`
+    pub(crate) fn relation(&self) -> Option<(BinOp, [Id; 2])> {
+        use YafcLanguage::*;
+
+        match self {
+            Eq(sides) => Some((BinOp::Eq, *sides)),
+            Lt(sides) => Some((BinOp::Lt, *sides)),
+            Le(sides) => Some((BinOp::Le, *sides)),
+            Gt(sides) => Some((BinOp::Gt, *sides)),
+            Ge(sides) => Some((BinOp::Ge, *sides)),
+            _ => None,
+        }
+    }
+
+    /// Collect the operands of a chain of the same associative operator as `self`
+    pub(crate) fn flatten(&self, expr: &RecExpr<YafcLanguage>, i: Id, out: &mut Vec<Id>) {
+        // an explicit stack, long chains are as deep as they are long
+        let mut stack = vec![i];
+        while let Some(i) = stack.pop() {
+            let node = &expr[i];
+            if std::mem::discriminant(node) == std::mem::discriminant(self) {
+                stack.extend(node.children().iter().rev());
+            } else {
+                out.push(i);
+            }
+        }
+    }
+
     pub fn needs_paren(prec: Option<u8>, outer_precedence: Option<u8>) -> bool {
         match (prec, outer_precedence) {
             (Some(prec), Some(outer_precedence)) => prec > outer_precedence,
@@ -117,15 +376,320 @@ impl YafcLanguage {
 }
 
 impl YafcExpr {
-    pub fn pretty(&self) -> Pretty {
+    pub fn pretty(&self) -> Pretty<'_> {
         self.pretty_opt(<_>::default())
     }
 
-    pub fn pretty_opt(&self, style: PrettyStyle) -> Pretty {
-        Pretty { expr: self, style }
+    pub fn pretty_opt(&self, style: PrettyStyle) -> Pretty<'_> {
+        Pretty {
+            expr: self,
+            style,
+            radix: <_>::default(),
+            raw: false,
+            factorial: <_>::default(),
+            fraction: <_>::default(),
+            spacing: None,
+        }
+    }
+
+    /// The terms of the top level sum, or the expression itself if it isn't one
+    pub fn as_sum(&self) -> Vec<YafcExpr> {
+        let root = self.root.expect("Not evaluated");
+        self.split(&YafcLanguage::Add([root, root]))
+    }
+
+    /// The factors of the top level product, or the expression itself if it isn't one
+    pub fn as_product(&self) -> Vec<YafcExpr> {
+        let root = self.root.expect("Not evaluated");
+        self.split(&YafcLanguage::Mul([root, root]))
+    }
+
+    /// Join the operands with `op` from left to right: `[a, b, c]` becomes `(a + b) + c`
+    ///
+    /// Returns `None` if there are no operands
+    pub fn from_operands(op: BinOp, operands: impl IntoIterator<Item = YafcExpr>) -> Option<Self> {
+        let mut out = YafcExpr::new();
+        let root = operands
+            .into_iter()
+            .map(|operand| out.copy_from(&operand, operand.root.expect("Not evaluated")))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .reduce(|lhs, rhs| out.make_bin(op, lhs, rhs))?;
+        out.root = Some(root);
+        Some(out)
+    }
+
+    /// The expression with its chains of `+`, `*`, `max` and `min` folded from left to
+    /// right: `a + (b + c)` becomes `(a + b) + c`
+    ///
+    /// The operators already take two operands each, so this is the tree that
+    /// binary-only consumers would build from the flattened operands
+    pub fn to_binary_tree(&self) -> YafcExpr {
+        let mut out = YafcExpr::new();
+        let root = self.to_binary_tree_rec(self.root.expect("Not evaluated"), &mut out);
+        out.root = Some(root);
+        out
+    }
+
+    fn to_binary_tree_rec(&self, i: Id, out: &mut YafcExpr) -> Id {
+        match &self.expr[i] {
+            node @ (YafcLanguage::Add(_)
+            | YafcLanguage::Mul(_)
+            | YafcLanguage::Max(_)
+            | YafcLanguage::Min(_)) => {
+                let mut operands = vec![];
+                node.flatten(self, i, &mut operands);
+                let operands: Vec<Id> = operands
+                    .into_iter()
+                    .map(|operand| self.to_binary_tree_rec(operand, out))
+                    .collect();
+                operands
+                    .into_iter()
+                    .reduce(|lhs, rhs| {
+                        let mut node = node.clone();
+                        node.children_mut().copy_from_slice(&[lhs, rhs]);
+                        out.add(node)
+                    })
+                    .unwrap()
+            }
+            node => {
+                let node = node
+                    .clone()
+                    .map_children(|child| self.to_binary_tree_rec(child, out));
+                out.add(node)
+            }
+        }
+    }
+
+    fn split(&self, op: &YafcLanguage) -> Vec<YafcExpr> {
+        let mut operands = vec![];
+        op.flatten(self, self.root.expect("Not evaluated"), &mut operands);
+        operands.into_iter().map(|i| self.subexpr(i)).collect()
+    }
+
+    /// A copy of the subexpression rooted at `i`
+    pub fn subexpr(&self, i: Id) -> YafcExpr {
+        let mut out = YafcExpr::new();
+        let root = out.copy_from(self, i);
+        out.root = Some(root);
+        out
+    }
+
+    /// Check if the variable `var` appears anywhere in the expression
+    pub fn contains_var(&self, var: &str) -> bool {
+        self.contains_var_at(self.root.expect("Not evaluated"), var)
+    }
+
+    /// Number of times the variable `var` appears in the expression: `x*x + x` gives 3
+    pub fn count_var(&self, var: &str) -> usize {
+        self.count_var_at(self.root.expect("Not evaluated"), var)
+    }
+
+    fn count_var_at(&self, i: Id, var: &str) -> usize {
+        match &self.expr[i] {
+            YafcLanguage::Var(v) => usize::from(v.as_str() == var),
+            node => node
+                .children()
+                .iter()
+                .map(|child| self.count_var_at(*child, var))
+                .sum(),
+        }
+    }
+
+    pub(crate) fn contains_var_at(&self, i: Id, var: &str) -> bool {
+        match &self.expr[i] {
+            YafcLanguage::Var(v) => v.as_str() == var,
+            node => node
+                .children()
+                .iter()
+                .any(|child| self.contains_var_at(*child, var)),
+        }
+    }
+
+    /// `0` or `-0`, without simplifying: `1 - 1` isn't zero here
+    pub fn is_zero(&self) -> bool {
+        self.is_num_at(self.root.expect("Not evaluated"), 0)
+    }
+
+    /// `1` or `--1`, without simplifying: `1 + 0` isn't one here
+    pub fn is_one(&self) -> bool {
+        self.is_num_at(self.root.expect("Not evaluated"), 1)
     }
 
+    /// `i` is `num`, possibly behind negations that cancel out
+    fn is_num_at(&self, i: Id, num: Num) -> bool {
+        match self.expr[i] {
+            YafcLanguage::Num(n) => n == num,
+            YafcLanguage::Mul([lhs, rhs]) if self.expr[lhs] == YafcLanguage::Num(-1) => num
+                .checked_neg()
+                .is_some_and(|neg| self.is_num_at(rhs, neg)),
+            _ => false,
+        }
+    }
+
+    /// Number of nodes in the expression tree, shared subexpressions are counted every time
+    pub fn node_count(&self) -> usize {
+        self.node_count_at(self.root.expect("Not evaluated"))
+    }
+
+    fn node_count_at(&self, i: Id) -> usize {
+        1 + self.expr[i]
+            .children()
+            .iter()
+            .map(|child| self.node_count_at(*child))
+            .sum::<usize>()
+    }
+
+    /// Weighted node count for picking the simpler of equivalent forms:
+    /// `+` costs 1, `*` 2 and `^` 4, `(x + 1)^2` is cheaper than `x^2 + 2*x + 1`
+    ///
+    /// Numbers and variables cost 1 and the other operators 2
+    pub fn cost(&self) -> u64 {
+        self.cost_at(self.root.expect("Not evaluated"))
+    }
+
+    fn cost_at(&self, i: Id) -> u64 {
+        let op_cost = match self.expr[i] {
+            YafcLanguage::Num(_)
+            | YafcLanguage::Float(_)
+            | YafcLanguage::Var(_)
+            | YafcLanguage::Const(_) => 1,
+            YafcLanguage::Add(_) => 1,
+            YafcLanguage::Mul(_) => 2,
+            YafcLanguage::Pow(_) => 4,
+            _ => 2,
+        };
+        op_cost
+            + self.expr[i]
+                .children()
+                .iter()
+                .map(|child| self.cost_at(*child))
+                .sum::<u64>()
+    }
+
+    /// A copy where structurally equal subexpressions are stored only once
+    pub fn hashconsed(&self) -> YafcExpr {
+        let mut out = YafcExpr::new();
+        let mut seen = HashMap::new();
+        let root = out.hashcons_rec(self, self.root.expect("Not evaluated"), &mut seen);
+        out.root = Some(root);
+        out
+    }
+
+    fn hashcons_rec(&mut self, src: &YafcExpr, i: Id, seen: &mut HashMap<YafcLanguage, Id>) -> Id {
+        let node = src.expr[i]
+            .clone()
+            .map_children(|child| self.hashcons_rec(src, child, seen));
+        *seen.entry(node.clone()).or_insert_with(|| self.add(node))
+    }
+
+    /// The `{:#}` form, cut off with `…` to at most `max_len` characters
+    ///
+    /// The cut is made at a space if there is one, and huge trees are only
+    /// printed as far as needed
+    pub fn format_truncated(&self, max_len: usize) -> String {
+        let mut out = Truncated {
+            out: String::new(),
+            len: 0,
+            // one more to tell if it was cut
+            max_len: max_len + 1,
+        };
+        if write!(out, "{self:#}").is_ok() && out.len <= max_len {
+            return out.out;
+        }
+        if max_len == 0 {
+            return String::new();
+        }
+
+        let mut cut: String = out.out.chars().take(max_len - 1).collect();
+        if let Some(space) = cut.rfind(' ') {
+            cut.truncate(space);
+        }
+        cut.push('…');
+        cut
+    }
+
+    /// Graphviz DOT representation of the expression tree
+    ///
+    /// `ordering=out` keeps the operands in their original order
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n    ordering=out;\n");
+        for (i, node) in self.expr.as_ref().iter().enumerate() {
+            writeln!(dot, "    {i} [label=\"{node}\"];").unwrap();
+            for child in node.children() {
+                writeln!(dot, "    {i} -> {child};").unwrap();
+            }
+        }
+        dot.push('}');
+        dot
+    }
+
+    /// JSON representation of the expression tree for frontends
+    ///
+    /// Numbers are `{"num": 2}` or `{"num": 0.5}`, variables `{"var": "x"}`, constants `{"const": "C"}`,
+    /// calls `{"call": "f", "args": [..]}` and the rest `{"op": "+", "args": [..]}`
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.write_json(self.root.expect("Not evaluated"), &mut json);
+        json
+    }
+
+    fn write_json(&self, i: Id, json: &mut String) {
+        let args = match &self.expr[i] {
+            YafcLanguage::Num(num) => {
+                write!(json, "{{\"num\": {num}}}").unwrap();
+                return;
+            }
+            YafcLanguage::Float(float) => {
+                write!(json, "{{\"num\": {float}}}").unwrap();
+                return;
+            }
+            YafcLanguage::Var(var) => {
+                write!(json, "{{\"var\": {}}}", json_string(var.as_str())).unwrap();
+                return;
+            }
+            YafcLanguage::Const(name) => {
+                write!(json, "{{\"const\": {}}}", json_string(name.as_str())).unwrap();
+                return;
+            }
+            YafcLanguage::Call(name, args) => {
+                write!(
+                    json,
+                    "{{\"call\": {}, \"args\": [",
+                    json_string(name.as_str())
+                )
+                .unwrap();
+                args
+            }
+            node => {
+                let op = json_string(&node.to_string());
+                write!(json, "{{\"op\": {op}, \"args\": [").unwrap();
+                node.children()
+            }
+        };
+        for (n, &arg) in args.iter().enumerate() {
+            if n != 0 {
+                json.push_str(", ");
+            }
+            self.write_json(arg, json);
+        }
+        json.push_str("]}");
+    }
+
+    /// The generated parser is a table driven LR parser and the sum/product
+    /// rules are left recursive, so long flat expressions like `a + b + ...`
+    /// are parsed with an explicit stack, not with recursion.
+    ///
+    /// `x(x + 1)` is `x * (x + 1)`, use [`Definitions::parse`] to call
+    /// user defined functions with single letter names
+    ///
+    /// [`Definitions::parse`]: crate::define::Definitions::parse
     pub fn parse_infix(s: &str) -> Result<Self, ParseError<usize, Token<'_>, String>> {
+        Ok(Self::parse_calls(s)?.calls_to_products(|_| false))
+    }
+
+    /// [`YafcExpr::parse_infix`] that keeps every `name(args)` a call
+    pub(crate) fn parse_calls(s: &str) -> Result<Self, ParseError<usize, Token<'_>, String>> {
         let parser = grammar::InputParser::new();
         let mut expr = YafcExpr::new();
         let root = parser.parse(&mut expr, s)?;
@@ -135,6 +699,75 @@ impl YafcExpr {
         Ok(expr)
     }
 
+    /// Turn calls like `x(x + 1)` with a single letter name and one argument into
+    /// implicit products, unless `is_function(name)`
+    ///
+    /// Longer names like `foo(x)` stay calls
+    pub(crate) fn calls_to_products(&self, is_function: impl Fn(&str) -> bool) -> Self {
+        let mut out = YafcExpr::new();
+        let mut ids = vec![];
+        for node in self.as_ref() {
+            let node = node.clone().map_children(|child| ids[usize::from(child)]);
+            let id = match node {
+                YafcLanguage::Call(name, args)
+                    if name.as_str().chars().count() == 1
+                        && args.len() == 1
+                        && !is_function(name.as_str()) =>
+                {
+                    let var = out.make_symbol(name.as_str());
+                    out.make_mul(var, args[0])
+                }
+                node => out.add(node),
+            };
+            ids.push(id);
+        }
+        out.root = self.root.map(|root| ids[usize::from(root)]);
+        out
+    }
+
+    /// [`YafcExpr::parse_infix`] that only accepts the variables and functions in `allowed`
+    ///
+    /// Sum indices are declared by the sum itself and are always allowed
+    pub fn parse_with<'a>(
+        s: &'a str,
+        allowed: &[&str],
+    ) -> Result<Self, ParseError<usize, Token<'a>, String>> {
+        let expr = Self::parse_infix(s)?;
+
+        let indices: Vec<Symbol> = expr
+            .as_ref()
+            .iter()
+            .filter_map(|node| match node {
+                YafcLanguage::Sum([index, ..]) => match expr[*index] {
+                    YafcLanguage::Var(index) => Some(index),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        let unknown = expr.as_ref().iter().find_map(|node| match node {
+            YafcLanguage::Var(name) | YafcLanguage::Call(name, _)
+                if !allowed.contains(&name.as_str()) && !indices.contains(name) =>
+            {
+                Some(name)
+            }
+            _ => None,
+        });
+
+        match unknown {
+            Some(name) => Err(ParseError::User {
+                error: format!("Unknown symbol `{name}`"),
+            }),
+            None => Ok(expr),
+        }
+    }
+
+    /// The operator and the sides if the expression is a relation like `a = b`
+    pub fn as_relation(&self) -> Option<(BinOp, YafcExpr, YafcExpr)> {
+        let (op, [lhs, rhs]) = self.expr[self.root.expect("Not evaluated")].relation()?;
+        Some((op, self.subexpr(lhs), self.subexpr(rhs)))
+    }
+
     pub(crate) fn new() -> Self {
         Self {
             expr: <_>::default(),
@@ -146,10 +779,36 @@ impl YafcExpr {
         self.add(YafcLanguage::Num(num))
     }
 
+    pub(crate) fn make_float(&mut self, float: f64) -> Id {
+        self.add(YafcLanguage::Float(Float(float)))
+    }
+
     pub(crate) fn make_var(&mut self, var: &str) -> Id {
         self.add(YafcLanguage::Var(var.into()))
     }
 
+    /// A variable as it is written, `i` is the imaginary unit and `e` is Euler's number
+    pub(crate) fn make_symbol(&mut self, var: &str) -> Id {
+        match var {
+            "i" => self.add(YafcLanguage::Imag),
+            "e" => self.add(YafcLanguage::Euler),
+            _ => self.make_var(var),
+        }
+    }
+
+    /// `i` or `e` as the variable again, in the body of a sum with that index
+    fn symbol_to_var(&mut self, i: Id, var: &str) -> Id {
+        let node = self.expr[i].clone();
+        match node {
+            YafcLanguage::Imag if var == "i" => self.make_var(var),
+            YafcLanguage::Euler if var == "e" => self.make_var(var),
+            node => {
+                let node = node.map_children(|child| self.symbol_to_var(child, var));
+                self.add(node)
+            }
+        }
+    }
+
     pub(crate) fn make_add(&mut self, lhs: Id, rhs: Id) -> Id {
         self.add(YafcLanguage::Add([lhs, rhs]))
     }
@@ -170,7 +829,7 @@ impl YafcExpr {
 
     pub(crate) fn make_inv(&mut self, v: Id) -> Id {
         let neg_1 = self.make_num(-1);
-        self.make_pow(neg_1, v)
+        self.make_pow(v, neg_1)
     }
 
     pub(crate) fn make_div(&mut self, lhs: Id, rhs: Id) -> Id {
@@ -182,6 +841,234 @@ impl YafcExpr {
         self.add(YafcLanguage::Pow([lhs, rhs]))
     }
 
+    pub(crate) fn make_sum(&mut self, index: &str, from: Id, to: Id, body: Id) -> Id {
+        // `i` is the index here, not the imaginary unit
+        let body = match index {
+            "i" | "e" => self.symbol_to_var(body, index),
+            _ => body,
+        };
+        let index = self.make_var(index);
+        self.add(YafcLanguage::Sum([index, from, to, body]))
+    }
+
+    /// Replace every occurrence of the variable `var` with `value`
+    ///
+    /// Sums that bind `var` as their index are left alone
+    pub fn substitute(&self, var: &str, value: &YafcExpr) -> YafcExpr {
+        let mut out = YafcExpr::new();
+        let root = self.substitute_rec(self.root.expect("Not evaluated"), var, value, &mut out);
+        out.root = Some(root);
+        out
+    }
+
+    fn substitute_rec(&self, i: Id, var: &str, value: &YafcExpr, out: &mut YafcExpr) -> Id {
+        use YafcLanguage::*;
+
+        match &self.expr[i] {
+            Var(v) if v.as_str() == var => out.copy_from(value, value.root.expect("Not evaluated")),
+            Sum([index, from, to, body]) if matches!(&self.expr[*index], Var(v) if v.as_str() == var) =>
+            {
+                let index = out.copy_from(self, *index);
+                let from = self.substitute_rec(*from, var, value, out);
+                let to = self.substitute_rec(*to, var, value, out);
+                let body = out.copy_from(self, *body);
+                out.add(Sum([index, from, to, body]))
+            }
+            node => {
+                let node = node
+                    .clone()
+                    .map_children(|child| self.substitute_rec(child, var, value, out));
+                out.add(node)
+            }
+        }
+    }
+
+    /// Rename the variable `from` to `to` everywhere, sum indices included
+    ///
+    /// `to` should be a fresh name: a sum over `from` would capture a `to` in its body
+    pub fn rename_var(&self, from: &str, to: &str) -> YafcExpr {
+        let mut out = YafcExpr::new();
+        let root = self.rename_var_rec(self.root.expect("Not evaluated"), from, to, &mut out);
+        out.root = Some(root);
+        out
+    }
+
+    fn rename_var_rec(&self, i: Id, from: &str, to: &str, out: &mut YafcExpr) -> Id {
+        match &self.expr[i] {
+            YafcLanguage::Var(var) if var.as_str() == from => out.make_var(to),
+            node => {
+                let node = node
+                    .clone()
+                    .map_children(|child| self.rename_var_rec(child, from, to, out));
+                out.add(node)
+            }
+        }
+    }
+
+    /// Apply `f` to every number in the expression: doubling `2*x + 3` gives `4*x + 6`
+    pub fn map_nums(&self, f: impl Fn(Num) -> Num) -> YafcExpr {
+        let mut out = YafcExpr::new();
+        let root = self.map_nums_rec(self.root.expect("Not evaluated"), &f, &mut out);
+        out.root = Some(root);
+        out
+    }
+
+    fn map_nums_rec(&self, i: Id, f: &impl Fn(Num) -> Num, out: &mut YafcExpr) -> Id {
+        match &self.expr[i] {
+            YafcLanguage::Num(num) => out.make_num(f(*num)),
+            node => {
+                let node = node
+                    .clone()
+                    .map_children(|child| self.map_nums_rec(child, f, out));
+                out.add(node)
+            }
+        }
+    }
+
+    /// Copy the subexpression `i` of `src` into `self`
+    pub(crate) fn copy_from(&mut self, src: &YafcExpr, i: Id) -> Id {
+        let node = src.expr[i]
+            .clone()
+            .map_children(|child| self.copy_from(src, child));
+        self.add(node)
+    }
+
+    pub(crate) fn make_bin(&mut self, op: BinOp, lhs: Id, rhs: Id) -> Id {
+        self.add(YafcLanguage::bin(op, [lhs, rhs]))
+    }
+
+    pub(crate) fn make_max(&mut self, lhs: Id, rhs: Id) -> Id {
+        self.add(YafcLanguage::Max([lhs, rhs]))
+    }
+
+    pub(crate) fn make_min(&mut self, lhs: Id, rhs: Id) -> Id {
+        self.add(YafcLanguage::Min([lhs, rhs]))
+    }
+
+    pub(crate) fn make_un(&mut self, op: UnOp, v: Id) -> Id {
+        self.add(YafcLanguage::un(op, v))
+    }
+
+    pub(crate) fn make_opaque(&mut self, v: Id) -> Id {
+        self.add(YafcLanguage::Opaque(v))
+    }
+
+    /// `name(args..)`, builtin functions like `sin(x)` get their own nodes
+    /// and anything else is left as a call to a user defined function
+    pub(crate) fn make_call(&mut self, name: &str, args: Vec<Id>) -> Result<Id, String> {
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format!(
+                    "Wrong number of arguments to `{name}`: expected {n}, got {}",
+                    args.len()
+                ))
+            }
+        };
+        let un = match name {
+            "floor" => Some(UnOp::Floor),
+            "ceil" => Some(UnOp::Ceil),
+            "sin" => Some(UnOp::Sin),
+            "cos" => Some(UnOp::Cos),
+            "tan" => Some(UnOp::Tan),
+            "abs" => Some(UnOp::Abs),
+            _ => None,
+        };
+        if let Some(op) = un {
+            arity(1)?;
+            return Ok(self.make_un(op, args[0]));
+        }
+
+        Ok(match name {
+            "max" => args
+                .into_iter()
+                .reduce(|lhs, rhs| self.make_max(lhs, rhs))
+                .unwrap(),
+            "min" => args
+                .into_iter()
+                .reduce(|lhs, rhs| self.make_min(lhs, rhs))
+                .unwrap(),
+            "opaque" => {
+                arity(1)?;
+                self.make_opaque(args[0])
+            }
+            "log" => {
+                arity(2)?;
+                self.make_bin(BinOp::Log, args[0], args[1])
+            }
+            "ln" => {
+                arity(1)?;
+                let e = self.add(YafcLanguage::Euler);
+                self.make_bin(BinOp::Log, e, args[0])
+            }
+            "exp" => {
+                arity(1)?;
+                let e = self.add(YafcLanguage::Euler);
+                self.make_pow(e, args[0])
+            }
+            "sqrt" => {
+                arity(1)?;
+                let one = self.make_num(1);
+                let two = self.make_num(2);
+                let half = self.make_div(one, two);
+                self.make_pow(args[0], half)
+            }
+            "piecewise" => {
+                if !args.len().is_multiple_of(2) {
+                    return Err("`piecewise` takes pairs of a condition and a value".into());
+                }
+                self.add(YafcLanguage::Piecewise(args))
+            }
+            "sum" if args.len() == 4 && self.expr[args[0]] == YafcLanguage::Imag => {
+                self.make_sum("i", args[1], args[2], args[3])
+            }
+            "sum" if args.len() == 4 && self.expr[args[0]] == YafcLanguage::Euler => {
+                self.make_sum("e", args[1], args[2], args[3])
+            }
+            "sum" => {
+                arity(4)?;
+                if !matches!(self.expr[args[0]], YafcLanguage::Var(_)) {
+                    return Err("The index of `sum` has to be a variable".into());
+                }
+                self.add(YafcLanguage::Sum([args[0], args[1], args[2], args[3]]))
+            }
+            _ => self.add(YafcLanguage::Call(name.into(), args)),
+        })
+    }
+
+    /// `[[a, b], [c, d]]` from its rows
+    pub(crate) fn make_matrix(&mut self, rows: Vec<Vec<Id>>) -> Result<Id, String> {
+        if rows.iter().any(|row| row.len() != rows[0].len()) {
+            return Err("Rows of a matrix must have the same length".into());
+        }
+        let rows = rows
+            .into_iter()
+            .map(|row| self.add(YafcLanguage::Row(row)))
+            .collect();
+        Ok(self.add(YafcLanguage::Matrix(rows)))
+    }
+
+    /// Remove all `opaque(..)` wrappers, so that the simplifier can touch them again
+    pub fn unwrap_opaque(&self) -> YafcExpr {
+        let mut out = YafcExpr::new();
+        let root = out.unwrap_opaque_rec(self, self.root.expect("Not evaluated"));
+        out.root = Some(root);
+        out
+    }
+
+    fn unwrap_opaque_rec(&mut self, src: &YafcExpr, i: Id) -> Id {
+        match &src.expr[i] {
+            YafcLanguage::Opaque(v) => self.unwrap_opaque_rec(src, *v),
+            node => {
+                let node = node
+                    .clone()
+                    .map_children(|child| self.unwrap_opaque_rec(src, child));
+                self.add(node)
+            }
+        }
+    }
+
     // pub(crate) fn make_fac(&mut self, v: Id) -> Id {
     //     self.add(YafcLanguage::Fac(v))
     // }
@@ -207,8 +1094,52 @@ impl fmt::Display for YafcExpr {
     }
 }
 
+/// Stops the formatting after `max_len` characters
+struct Truncated {
+    out: String,
+    len: usize,
+    max_len: usize,
+}
+
+impl fmt::Write for Truncated {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.len == self.max_len {
+                return Err(fmt::Error);
+            }
+            self.out.push(c);
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+/// `s` as a quoted JSON string
+pub(crate) fn json_string(s: &str) -> String {
+    let mut json = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
 impl fmt::Display for Pretty<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.raw && self.expr.has_negated_sum() {
+            let flat = self.expr.flatten_negated_sums();
+            return Pretty {
+                expr: &flat,
+                ..self.clone()
+            }
+            .fmt(f);
+        }
+
         let root = self.expr.root.expect("Not evaluated");
         match self.style {
             PrettyStyle::Infix => self.fmt_rec_infix(f, root, None),
@@ -217,7 +1148,278 @@ impl fmt::Display for Pretty<'_> {
     }
 }
 
+impl YafcExpr {
+    /// If there is a term like `-1 * (b + c)` in a sum
+    fn has_negated_sum(&self) -> bool {
+        self.as_ref().iter().any(|node| match node {
+            YafcLanguage::Add([_, rhs]) => self.negated_sum(*rhs).is_some(),
+            _ => false,
+        })
+    }
+
+    /// The sum `b + c` of the term `-1 * (b + c)`
+    fn negated_sum(&self, i: Id) -> Option<Id> {
+        match self.expr[i] {
+            YafcLanguage::Mul([lhs, rhs]) if self.expr[lhs] == YafcLanguage::Num(-1) => {
+                matches!(self.expr[rhs], YafcLanguage::Add(_)).then_some(rhs)
+            }
+            _ => None,
+        }
+    }
+
+    /// `a + -1 * (b + c)` as `a + -1 * b + -1 * c`, which is printed as `a - b - c`
+    /// instead of `a - (b + c)`
+    fn flatten_negated_sums(&self) -> YafcExpr {
+        let mut out = YafcExpr::new();
+        let mut ids = vec![];
+        for node in self.as_ref() {
+            let node = node.clone().map_children(|child| ids[usize::from(child)]);
+            let id = match node {
+                YafcLanguage::Add([lhs, rhs]) if out.negated_sum(rhs).is_some() => {
+                    let sum = out.negated_sum(rhs).unwrap();
+                    let mut terms = vec![];
+                    out.expr[sum].flatten(&out.expr, sum, &mut terms);
+                    terms.into_iter().fold(lhs, |acc, term| {
+                        let term = out.make_negated(term);
+                        out.make_add(acc, term)
+                    })
+                }
+                node => out.add(node),
+            };
+            ids.push(id);
+        }
+        out.root = self.root.map(|root| ids[usize::from(root)]);
+        out
+    }
+
+    /// `-i` without stacking up signs: `-1 * b` gives `b` and `-2 * b` gives `2 * b`
+    fn make_negated(&mut self, i: Id) -> Id {
+        match self.expr[i] {
+            YafcLanguage::Num(num) if num.checked_neg().is_some() => self.make_num(-num),
+            YafcLanguage::Mul([lhs, rhs]) => match self.expr[lhs] {
+                YafcLanguage::Num(-1) => rhs,
+                YafcLanguage::Num(num) if num.checked_neg().is_some() => {
+                    let num = self.make_num(-num);
+                    self.make_mul(num, rhs)
+                }
+                _ => self.make_neg(i),
+            },
+            _ => self.make_neg(i),
+        }
+    }
+}
+
+enum Negated {
+    /// `-1 * b` as `b`
+    Expr(Id),
+
+    /// `-n` as `n`
+    Num(Num),
+
+    /// `-n * b` as `n * b`
+    Scaled(Num, Id),
+}
+
 impl Pretty<'_> {
+    /// Base used for numeric literals
+    pub fn with_radix(mut self, radix: Radix) -> Self {
+        self.radix = radix;
+        self
+    }
+
+    /// Print the tree as it is stored, `a + -1 * b` instead of `a - b`
+    /// and `a * b ^ -1` instead of `a / b`
+    pub fn with_raw(mut self, raw: bool) -> Self {
+        self.raw = raw;
+        self
+    }
+
+    /// How `n!` is printed
+    pub fn with_factorial_style(mut self, factorial: FactorialStyle) -> Self {
+        self.factorial = factorial;
+        self
+    }
+
+    /// How numeric fractions like `7 / 2` are printed
+    pub fn with_fraction_style(mut self, fraction: FractionStyle) -> Self {
+        self.fraction = fraction;
+        self
+    }
+
+    /// Spaces around the operators, without this `{:#}` is loose and `{}` tight
+    pub fn with_spacing(mut self, spacing: Spacing) -> Self {
+        self.spacing = Some(spacing);
+        self
+    }
+
+    /// Whole part, numerator and denominator of `numer / denom_at` as a mixed number,
+    /// if that is how it is printed
+    fn mixed(&self, numer: Num, denom_at: Id) -> Option<(Num, Num, Num)> {
+        if self.fraction != FractionStyle::Mixed {
+            return None;
+        }
+        let YafcLanguage::Num(denom) = self.expr.expr[self.divisor(denom_at)?] else {
+            return None;
+        };
+        if denom <= 1 || numer.checked_abs()? <= denom {
+            return None;
+        }
+        // the sign is carried by the whole part: `-7/2` is `-3 1/2`
+        Some((numer / denom, (numer % denom).abs(), denom))
+    }
+
+    /// [`Pretty::mixed`] for the whole node `i`, `7 * 2^-1`
+    fn mixed_at(&self, i: Id) -> Option<(Num, Num, Num)> {
+        let YafcLanguage::Mul([lhs, rhs]) = self.expr.expr[i] else {
+            return None;
+        };
+        let YafcLanguage::Num(numer) = self.expr.expr[lhs] else {
+            return None;
+        };
+        self.mixed(numer, rhs)
+    }
+
+    fn fmt_mixed(
+        &self,
+        f: &mut fmt::Formatter,
+        (whole, numer, denom): (Num, Num, Num),
+    ) -> fmt::Result {
+        let decimal = self.decimal();
+        decimal.fmt_num(f, whole)?;
+        match self.style {
+            PrettyStyle::Infix => write!(f, " ")?,
+            PrettyStyle::LaTeX => write!(f, "\\frac{{")?,
+        }
+        decimal.fmt_num(f, numer)?;
+        match self.style {
+            PrettyStyle::Infix => write!(f, "/")?,
+            PrettyStyle::LaTeX => write!(f, "}}{{")?,
+        }
+        decimal.fmt_num(f, denom)?;
+        match self.style {
+            PrettyStyle::Infix => Ok(()),
+            PrettyStyle::LaTeX => write!(f, "}}"),
+        }
+    }
+
+    fn fmt_num(&self, f: &mut fmt::Formatter, num: Num) -> fmt::Result {
+        let sign = if num < 0 { "-" } else { "" };
+        let abs = num.unsigned_abs();
+        match self.radix {
+            Radix::Dec => write!(f, "{num}"),
+            Radix::Hex => write!(f, "{sign}0x{abs:x}"),
+            Radix::Bin => write!(f, "{sign}0b{abs:b}"),
+            Radix::Oct => write!(f, "{sign}0o{abs:o}"),
+        }
+    }
+
+    fn loose(&self, f: &fmt::Formatter) -> bool {
+        self.spacing
+            .map_or(f.alternate(), |spacing| spacing == Spacing::Loose)
+    }
+
+    fn fmt_op(&self, f: &mut fmt::Formatter, op: &str) -> fmt::Result {
+        if self.loose(f) {
+            write!(f, " {op} ")
+        } else {
+            write!(f, "{op}")
+        }
+    }
+
+    /// A minus sign, with a space if `next` starts with one too: `a - -b` is never `a--b`
+    fn fmt_minus(&self, f: &mut fmt::Formatter, next: Id) -> fmt::Result {
+        write!(f, "-")?;
+        if self.starts_with_minus(next) {
+            write!(f, " ")?;
+        }
+        Ok(())
+    }
+
+    /// If the infix form of `i` starts with a minus sign
+    fn starts_with_minus(&self, mut i: Id) -> bool {
+        loop {
+            match self.expr.expr[i] {
+                YafcLanguage::Num(num) => return num < 0,
+                YafcLanguage::Mul(_) if self.mixed_at(i).is_some() => {
+                    return self.mixed_at(i).unwrap().0 < 0
+                }
+                YafcLanguage::Mul([lhs, _]) => i = lhs,
+                _ => return false,
+            }
+        }
+    }
+
+    /// Terms that can be printed as a subtraction `a - b` instead of `a + -1 * b`
+    fn negated(&self, i: Id) -> Option<Negated> {
+        if self.raw {
+            return None;
+        }
+        match &self.expr.expr[i] {
+            YafcLanguage::Num(num) if *num < 0 => Some(Negated::Num(num.checked_neg()?)),
+            YafcLanguage::Mul([lhs, rhs]) => match self.expr.expr[*lhs] {
+                YafcLanguage::Num(-1) => Some(Negated::Expr(*rhs)),
+                YafcLanguage::Num(num) if num < 0 => {
+                    Some(Negated::Scaled(num.checked_neg()?, *rhs))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The last side of `rhs` if `lhs and rhs` can be printed as a chain:
+    /// `a < b and b < c` is `a < b < c`
+    fn chain_tail(&self, lhs: Id, rhs: Id) -> Option<Id> {
+        let expr = &self.expr.expr;
+        let mut last = lhs;
+        while let YafcLanguage::And([_, rhs]) = expr[last] {
+            last = rhs;
+        }
+        let (_, [_, last]) = expr[last].relation()?;
+        let (_, [first, tail]) = expr[rhs].relation()?;
+        (self.expr.subexpr(last) == self.expr.subexpr(first)).then_some(tail)
+    }
+
+    /// A negative number, the base of `(-1)^n` needs the parentheses even though
+    /// numbers don't have a precedence
+    fn negative_num(&self, i: Id) -> bool {
+        matches!(self.expr.expr[i], YafcLanguage::Num(num) if num < 0)
+    }
+
+    /// Denominators and numeric fractions are printed in decimal, `7/2` and `x/2`
+    /// instead of `0x7/0x2` and `x/0x2`
+    fn decimal(&self) -> Self {
+        Self {
+            radix: Radix::Dec,
+            ..self.clone()
+        }
+    }
+
+    /// The printer of `numer` over the divisor `divisor_at`, [`Pretty::decimal`]
+    /// if the fraction is numeric
+    fn numerator(&self, numer: &YafcLanguage, divisor_at: Id) -> Self {
+        let divisor = self
+            .divisor(divisor_at)
+            .map(|divisor| &self.expr.expr[divisor]);
+        match (numer, divisor) {
+            (YafcLanguage::Num(_), Some(YafcLanguage::Num(_))) => self.decimal(),
+            _ => self.clone(),
+        }
+    }
+
+    /// Factors that can be printed as a division `a / b` instead of `a * b^-1`
+    fn divisor(&self, i: Id) -> Option<Id> {
+        if self.raw {
+            return None;
+        }
+        match &self.expr.expr[i] {
+            YafcLanguage::Pow([base, exp]) if self.expr.expr[*exp] == YafcLanguage::Num(-1) => {
+                Some(*base)
+            }
+            _ => None,
+        }
+    }
+
     fn fmt_rec_infix(
         &self,
         f: &mut fmt::Formatter,
@@ -228,6 +1430,11 @@ impl Pretty<'_> {
 
         let expr = &self.expr.expr[i];
         let (op, prec) = expr.op_and_prec();
+        // `b^-1` is printed as `1 / b`
+        let prec = match self.divisor(i) {
+            Some(_) => BinOp::Mul.precedence().into(),
+            None => prec,
+        };
         let needs_paren = YafcLanguage::needs_paren(prec, outer_precedence);
 
         if needs_paren {
@@ -235,21 +1442,176 @@ impl Pretty<'_> {
         }
 
         match expr {
-            Num(num) => write!(f, "{num}")?,
-            Var(var) => write!(f, "{var}")?,
-            Add([lhs, rhs]) | Mul([lhs, rhs]) | Pow([lhs, rhs]) => {
-                self.fmt_rec_infix(f, *lhs, prec)?;
-                if f.alternate() {
-                    write!(f, " {op} ")?;
+            Num(num) => self.fmt_num(f, *num)?,
+            Float(float) => write!(f, "{float}")?,
+            Var(var) | Const(var) => write!(f, "{var}")?,
+            Imag => write!(f, "i")?,
+            Euler => write!(f, "e")?,
+            // long sums and products are nested on the left, their left sides are
+            // printed in a loop so that 10000 terms don't need 10000 stack frames
+            Add(_) => {
+                let spine = self.left_spine(i);
+                let last = *spine.last().unwrap();
+                self.fmt_rec_infix(f, self.lhs(last), prec)?;
+                for &node in spine.iter().rev() {
+                    self.fmt_add_rhs(f, node, prec)?;
+                }
+            }
+            Mul(_) if self.mixed_at(i).is_some() => self.fmt_mixed(f, self.mixed_at(i).unwrap())?,
+            Mul(_) => {
+                let spine = self.left_spine(i);
+                let last = *spine.last().unwrap();
+                let lhs = self.lhs(last);
+                match self.divisor(self.rhs(last)) {
+                    None if !self.raw && self.expr.expr[lhs] == Num(-1) => {
+                        self.fmt_minus(f, self.rhs(last))?;
+                        self.fmt_rec_infix(f, self.rhs(last), prec)?;
+                    }
+                    _ => {
+                        self.numerator(&self.expr.expr[lhs], self.rhs(last))
+                            .fmt_rec_infix(f, lhs, prec)?;
+                        self.fmt_mul_rhs(f, last, prec)?;
+                    }
+                }
+                for &node in spine.iter().rev().skip(1) {
+                    self.fmt_mul_rhs(f, node, prec)?;
+                }
+            }
+            Pow(_) if self.divisor(i).is_some() => {
+                write!(f, "1")?;
+                self.fmt_op(f, "/")?;
+                let base = self.divisor(i).unwrap();
+                self.decimal()
+                    .fmt_rec_infix(f, base, prec.map(|prec| prec - 1))?;
+            }
+            Pow([lhs, rhs]) => {
+                // `^` is right associative, (a^b)^c needs the parentheses
+                if self.negative_num(*lhs) {
+                    write!(f, "(")?;
+                    self.fmt_rec_infix(f, *lhs, None)?;
+                    write!(f, ")")?;
                 } else {
-                    write!(f, "{op}")?;
+                    self.fmt_rec_infix(f, *lhs, prec.map(|prec| prec - 1))?;
                 }
+                self.fmt_op(f, "^")?;
                 self.fmt_rec_infix(f, *rhs, prec)?;
             }
+            Eq([lhs, rhs]) | Lt([lhs, rhs]) | Le([lhs, rhs]) | Gt([lhs, rhs]) | Ge([lhs, rhs]) => {
+                // relations don't chain
+                let side_prec = prec.map(|prec| prec - 1);
+                self.fmt_rec_infix(f, *lhs, side_prec)?;
+                self.fmt_op(f, &expr.to_string())?;
+                self.fmt_rec_infix(f, *rhs, side_prec)?;
+            }
+            And([lhs, rhs]) => {
+                self.fmt_rec_infix(f, *lhs, prec)?;
+                match self.chain_tail(*lhs, *rhs) {
+                    Some(tail) => {
+                        let side_prec = BinOp::Eq.precedence() - 1;
+                        self.fmt_op(f, &self.expr.expr[*rhs].to_string())?;
+                        self.fmt_rec_infix(f, tail, side_prec.into())?;
+                    }
+                    None => {
+                        write!(f, " {expr} ")?;
+                        self.fmt_rec_infix(f, *rhs, prec.map(|prec| prec - 1))?;
+                    }
+                }
+            }
+            Or([lhs, rhs]) => {
+                self.fmt_rec_infix(f, *lhs, prec)?;
+                write!(f, " {expr} ")?;
+                self.fmt_rec_infix(f, *rhs, prec.map(|prec| prec - 1))?;
+            }
+            Fac(v) if self.factorial == FactorialStyle::Gamma => {
+                write!(f, "Γ(")?;
+                self.fmt_rec_infix(f, *v, BinOp::Add.precedence().into())?;
+                self.fmt_op(f, "+")?;
+                write!(f, "1)")?;
+            }
+            // `(x!)!` keeps the parentheses, `x!!` is the double factorial
             Fac(v) => {
-                self.fmt_rec_infix(f, *v, prec)?;
+                self.fmt_rec_infix(f, *v, prec.map(|prec| prec - 1))?;
                 write!(f, "{op}")?;
             }
+            DoubleFac(v) => {
+                self.fmt_rec_infix(f, *v, prec.map(|prec| prec - 1))?;
+                write!(f, "{op}{op}")?;
+            }
+            Max(_) | Min(_) => {
+                let mut operands = vec![];
+                expr.flatten(&self.expr.expr, i, &mut operands);
+
+                write!(f, "{expr}(")?;
+                for (n, operand) in operands.into_iter().enumerate() {
+                    if n != 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_rec_infix(f, operand, None)?;
+                }
+                write!(f, ")")?;
+            }
+            Log([base, arg]) if self.expr.expr[*base] == Euler => {
+                write!(f, "ln(")?;
+                self.fmt_rec_infix(f, *arg, None)?;
+                write!(f, ")")?;
+            }
+            Log([base, arg]) => {
+                write!(f, "log(")?;
+                self.fmt_rec_infix(f, *base, None)?;
+                write!(f, ", ")?;
+                self.fmt_rec_infix(f, *arg, None)?;
+                write!(f, ")")?;
+            }
+            Abs(v) => {
+                write!(f, "|")?;
+                self.fmt_rec_infix(f, *v, None)?;
+                write!(f, "|")?;
+            }
+            Floor(v) | Ceil(v) | Sin(v) | Cos(v) | Tan(v) | Opaque(v) => {
+                write!(f, "{expr}(")?;
+                self.fmt_rec_infix(f, *v, None)?;
+                write!(f, ")")?;
+            }
+            Sum(operands) => {
+                write!(f, "sum(")?;
+                for (n, operand) in operands.iter().enumerate() {
+                    if n != 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_rec_infix(f, *operand, None)?;
+                }
+                write!(f, ")")?;
+            }
+            Call(name, args) => {
+                write!(f, "{name}(")?;
+                for (n, arg) in args.iter().enumerate() {
+                    if n != 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_rec_infix(f, *arg, None)?;
+                }
+                write!(f, ")")?;
+            }
+            Piecewise(args) => {
+                write!(f, "piecewise(")?;
+                for (n, arg) in args.iter().enumerate() {
+                    if n != 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_rec_infix(f, *arg, None)?;
+                }
+                write!(f, ")")?;
+            }
+            Matrix(items) | Row(items) => {
+                write!(f, "[")?;
+                for (n, item) in items.iter().enumerate() {
+                    if n != 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_rec_infix(f, *item, None)?;
+                }
+                write!(f, "]")?;
+            }
         };
 
         if needs_paren {
@@ -259,6 +1621,106 @@ impl Pretty<'_> {
         Ok(())
     }
 
+    fn lhs(&self, i: Id) -> Id {
+        self.expr.expr[i].children()[0]
+    }
+
+    fn rhs(&self, i: Id) -> Id {
+        self.expr.expr[i].children()[1]
+    }
+
+    /// `i` and the sums or products down its left side that are printed without
+    /// parentheses, the innermost last
+    fn left_spine(&self, i: Id) -> Vec<Id> {
+        let expr = &self.expr.expr;
+        let mut spine = vec![i];
+        loop {
+            let lhs = self.lhs(*spine.last().unwrap());
+            let same_op = std::mem::discriminant(&expr[lhs]) == std::mem::discriminant(&expr[i]);
+            if !same_op || self.mixed_at(lhs).is_some() {
+                return spine;
+            }
+            spine.push(lhs);
+        }
+    }
+
+    /// The `+ b` or `- b` of the sum `i`, after its left side
+    fn fmt_add_rhs(&self, f: &mut fmt::Formatter, i: Id, prec: Option<u8>) -> fmt::Result {
+        use YafcLanguage::*;
+
+        // `a - (b + c)` keeps the parentheses
+        let sub_prec = prec.map(|prec| prec - 1);
+        match self.negated(self.rhs(i)) {
+            Some(Negated::Expr(rhs)) => {
+                self.fmt_op(f, "-")?;
+                if !self.loose(f) && self.starts_with_minus(rhs) {
+                    write!(f, " ")?;
+                }
+                self.fmt_rec_infix(f, rhs, sub_prec)
+            }
+            Some(Negated::Num(num)) => {
+                self.fmt_op(f, "-")?;
+                self.fmt_num(f, num)
+            }
+            Some(Negated::Scaled(num, rhs)) if self.mixed(num, rhs).is_some() => {
+                self.fmt_op(f, "-")?;
+                self.fmt_mixed(f, self.mixed(num, rhs).unwrap())
+            }
+            Some(Negated::Scaled(num, rhs)) => {
+                self.fmt_op(f, "-")?;
+                self.numerator(&Num(num), rhs).fmt_num(f, num)?;
+                match self.divisor(rhs) {
+                    Some(rhs) => {
+                        self.fmt_op(f, "/")?;
+                        self.decimal()
+                            .fmt_rec_infix(f, rhs, BinOp::Pow.precedence().into())
+                    }
+                    None => {
+                        self.fmt_op(f, "*")?;
+                        self.fmt_rec_infix(f, rhs, BinOp::Mul.precedence().into())
+                    }
+                }
+            }
+            None => {
+                self.fmt_op(f, "+")?;
+                self.fmt_rec_infix(f, self.rhs(i), prec)
+            }
+        }
+    }
+
+    /// The `* b` or `/ b` of the product `i`, after its left side
+    fn fmt_mul_rhs(&self, f: &mut fmt::Formatter, i: Id, prec: Option<u8>) -> fmt::Result {
+        match self.divisor(self.rhs(i)) {
+            // `a / (b * c)` keeps the parentheses
+            Some(divisor) => {
+                self.fmt_op(f, "/")?;
+                self.decimal()
+                    .fmt_rec_infix(f, divisor, prec.map(|prec| prec - 1))
+            }
+            None => {
+                self.fmt_op(f, "*")?;
+                self.fmt_rec_infix(f, self.rhs(i), prec)
+            }
+        }
+    }
+
+    fn latex_op(op: char) -> &'static str {
+        match op {
+            '+' => "+",
+            '*' => "\\cdot",
+            '^' => "^",
+            '!' => "!",
+            '=' => "=",
+            '<' => "<",
+            '≤' => "\\le",
+            '>' => ">",
+            '≥' => "\\ge",
+            '∧' => "\\land",
+            '∨' => "\\lor",
+            _ => "",
+        }
+    }
+
     fn fmt_rec_latex(
         &self,
         f: &mut fmt::Formatter,
@@ -269,15 +1731,14 @@ impl Pretty<'_> {
 
         let expr = &self.expr.expr[i];
         let (op, prec) = expr.op_and_prec();
+        // `b^-1` is printed as `1 / b`
+        let prec = match self.divisor(i) {
+            Some(_) => BinOp::Mul.precedence().into(),
+            None => prec,
+        };
         let needs_paren = YafcLanguage::needs_paren(prec, outer_precedence);
 
-        let op = match op {
-            '+' => "+",
-            '*' => "\\cdot",
-            '^' => "^",
-            '!' => "!",
-            _ => "",
-        };
+        let op = Self::latex_op(op);
 
         write!(f, "{{")?;
         if needs_paren {
@@ -285,21 +1746,222 @@ impl Pretty<'_> {
         }
 
         match expr {
-            Num(num) => write!(f, "{num}")?,
-            Var(var) => write!(f, "{var}")?,
-            Add([lhs, rhs]) | Mul([lhs, rhs]) | Pow([lhs, rhs]) => {
+            Num(num) => self.fmt_num(f, *num)?,
+            Float(float) => write!(f, "{float}")?,
+            Var(var) | Const(var) => write!(f, "{var}")?,
+            Imag => write!(f, "i")?,
+            Euler => write!(f, "e")?,
+            Add([lhs, rhs]) => {
                 self.fmt_rec_latex(f, *lhs, prec)?;
-                if f.alternate() {
-                    write!(f, " {op} ")?;
+                // `a - (b + c)` keeps the parentheses
+                let sub_prec = prec.map(|prec| prec - 1);
+                match self.negated(*rhs) {
+                    Some(Negated::Expr(rhs)) => {
+                        self.fmt_op(f, "-")?;
+                        self.fmt_rec_latex(f, rhs, sub_prec)?;
+                    }
+                    Some(Negated::Num(num)) => {
+                        self.fmt_op(f, "-")?;
+                        self.fmt_num(f, num)?;
+                    }
+                    Some(Negated::Scaled(num, rhs)) if self.mixed(num, rhs).is_some() => {
+                        self.fmt_op(f, "-")?;
+                        self.fmt_mixed(f, self.mixed(num, rhs).unwrap())?;
+                    }
+                    Some(Negated::Scaled(num, rhs)) => {
+                        self.fmt_op(f, "-")?;
+                        match self.divisor(rhs) {
+                            Some(divisor) => {
+                                write!(f, "\\frac{{")?;
+                                self.numerator(&Num(num), rhs).fmt_num(f, num)?;
+                                write!(f, "}}")?;
+                                self.decimal().fmt_rec_latex(f, divisor, None)?;
+                            }
+                            None => {
+                                self.fmt_num(f, num)?;
+                                self.fmt_op(f, "\\cdot")?;
+                                self.fmt_rec_latex(f, rhs, BinOp::Mul.precedence().into())?;
+                            }
+                        }
+                    }
+                    None => {
+                        self.fmt_op(f, op)?;
+                        self.fmt_rec_latex(f, *rhs, prec)?;
+                    }
+                }
+            }
+            Mul(_) if self.mixed_at(i).is_some() => self.fmt_mixed(f, self.mixed_at(i).unwrap())?,
+            Mul([lhs, rhs]) => match self.divisor(*rhs) {
+                Some(divisor) => {
+                    write!(f, "\\frac")?;
+                    self.numerator(&self.expr.expr[*lhs], *rhs)
+                        .fmt_rec_latex(f, *lhs, None)?;
+                    self.decimal().fmt_rec_latex(f, divisor, None)?;
+                }
+                None => {
+                    if !self.raw && self.expr.expr[*lhs] == Num(-1) {
+                        write!(f, "-")?;
+                    } else {
+                        self.fmt_rec_latex(f, *lhs, prec)?;
+                        self.fmt_op(f, op)?;
+                    }
+                    self.fmt_rec_latex(f, *rhs, prec)?;
+                }
+            },
+            Pow(_) if self.divisor(i).is_some() => {
+                write!(f, "\\frac{{1}}")?;
+                let base = self.divisor(i).unwrap();
+                self.decimal().fmt_rec_latex(f, base, None)?;
+            }
+            Pow([lhs, rhs]) => {
+                // `^` is right associative, (a^b)^c needs the parentheses
+                if self.negative_num(*lhs) {
+                    write!(f, "\\left(")?;
+                    self.fmt_rec_latex(f, *lhs, None)?;
+                    write!(f, "\\right)")?;
                 } else {
-                    write!(f, "{op}")?;
+                    self.fmt_rec_latex(f, *lhs, prec.map(|prec| prec - 1))?;
                 }
+                self.fmt_op(f, op)?;
                 self.fmt_rec_latex(f, *rhs, prec)?;
             }
+            Eq([lhs, rhs]) | Lt([lhs, rhs]) | Le([lhs, rhs]) | Gt([lhs, rhs]) | Ge([lhs, rhs]) => {
+                // relations don't chain
+                let side_prec = prec.map(|prec| prec - 1);
+                self.fmt_rec_latex(f, *lhs, side_prec)?;
+                self.fmt_op(f, op)?;
+                self.fmt_rec_latex(f, *rhs, side_prec)?;
+            }
+            And([lhs, rhs]) => {
+                self.fmt_rec_latex(f, *lhs, prec)?;
+                match self.chain_tail(*lhs, *rhs) {
+                    Some(tail) => {
+                        let side_prec = BinOp::Eq.precedence() - 1;
+                        let (tail_op, _) = self.expr.expr[*rhs].op_and_prec();
+                        self.fmt_op(f, Self::latex_op(tail_op))?;
+                        self.fmt_rec_latex(f, tail, side_prec.into())?;
+                    }
+                    None => {
+                        self.fmt_op(f, op)?;
+                        self.fmt_rec_latex(f, *rhs, prec.map(|prec| prec - 1))?;
+                    }
+                }
+            }
+            Or([lhs, rhs]) => {
+                self.fmt_rec_latex(f, *lhs, prec)?;
+                self.fmt_op(f, op)?;
+                self.fmt_rec_latex(f, *rhs, prec.map(|prec| prec - 1))?;
+            }
+            Fac(v) if self.factorial == FactorialStyle::Gamma => {
+                write!(f, "\\Gamma\\left(")?;
+                self.fmt_rec_latex(f, *v, BinOp::Add.precedence().into())?;
+                self.fmt_op(f, "+")?;
+                write!(f, "{{1}}\\right)")?;
+            }
             Fac(v) => {
-                self.fmt_rec_latex(f, *v, prec)?;
+                self.fmt_rec_latex(f, *v, prec.map(|prec| prec - 1))?;
                 write!(f, "{op}")?;
             }
+            DoubleFac(v) => {
+                self.fmt_rec_latex(f, *v, prec.map(|prec| prec - 1))?;
+                write!(f, "{op}{op}")?;
+            }
+            Floor(v) => {
+                write!(f, "\\left\\lfloor ")?;
+                self.fmt_rec_latex(f, *v, None)?;
+                write!(f, " \\right\\rfloor")?;
+            }
+            Ceil(v) => {
+                write!(f, "\\left\\lceil ")?;
+                self.fmt_rec_latex(f, *v, None)?;
+                write!(f, " \\right\\rceil")?;
+            }
+            Log([base, arg]) if self.expr.expr[*base] == Euler => {
+                write!(f, "\\ln\\left(")?;
+                self.fmt_rec_latex(f, *arg, None)?;
+                write!(f, "\\right)")?;
+            }
+            Log([base, arg]) => {
+                write!(f, "\\log_")?;
+                self.fmt_rec_latex(f, *base, None)?;
+                write!(f, "\\left(")?;
+                self.fmt_rec_latex(f, *arg, None)?;
+                write!(f, "\\right)")?;
+            }
+            Abs(v) => {
+                write!(f, "\\left| ")?;
+                self.fmt_rec_latex(f, *v, None)?;
+                write!(f, " \\right|")?;
+            }
+            Sin(v) | Cos(v) | Tan(v) => {
+                write!(f, "\\{expr}\\left(")?;
+                self.fmt_rec_latex(f, *v, None)?;
+                write!(f, "\\right)")?;
+            }
+            Opaque(v) => self.fmt_rec_latex(f, *v, prec)?,
+            Max(_) | Min(_) => {
+                let mut operands = vec![];
+                expr.flatten(&self.expr.expr, i, &mut operands);
+
+                write!(f, "\\{expr}\\left(")?;
+                for (n, operand) in operands.into_iter().enumerate() {
+                    if n != 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_rec_latex(f, operand, None)?;
+                }
+                write!(f, "\\right)")?;
+            }
+            Sum([index, from, to, body]) => {
+                write!(f, "\\sum_{{")?;
+                self.fmt_rec_latex(f, *index, None)?;
+                write!(f, "=")?;
+                self.fmt_rec_latex(f, *from, None)?;
+                write!(f, "}}^")?;
+                self.fmt_rec_latex(f, *to, None)?;
+                write!(f, " ")?;
+                self.fmt_rec_latex(f, *body, None)?;
+            }
+            Call(name, args) => {
+                write!(f, "\\operatorname{{{name}}}\\left(")?;
+                for (n, arg) in args.iter().enumerate() {
+                    if n != 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_rec_latex(f, *arg, None)?;
+                }
+                write!(f, "\\right)")?;
+            }
+            Piecewise(args) => {
+                write!(f, "\\begin{{cases}}")?;
+                for (n, branch) in args.chunks(2).enumerate() {
+                    if n != 0 {
+                        write!(f, " \\\\ ")?;
+                    }
+                    self.fmt_rec_latex(f, branch[1], None)?;
+                    write!(f, " & ")?;
+                    self.fmt_rec_latex(f, branch[0], None)?;
+                }
+                write!(f, "\\end{{cases}}")?;
+            }
+            Matrix(rows) => {
+                write!(f, "\\begin{{pmatrix}}")?;
+                for (n, row) in rows.iter().enumerate() {
+                    if n != 0 {
+                        write!(f, " \\\\ ")?;
+                    }
+                    self.fmt_rec_latex(f, *row, None)?;
+                }
+                write!(f, "\\end{{pmatrix}}")?;
+            }
+            Row(items) => {
+                for (n, item) in items.iter().enumerate() {
+                    if n != 0 {
+                        write!(f, " & ")?;
+                    }
+                    self.fmt_rec_latex(f, *item, None)?;
+                }
+            }
         };
 
         if needs_paren {
@@ -310,3 +1972,168 @@ impl Pretty<'_> {
         Ok(())
     }
 }
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::{FractionStyle, Radix, YafcExpr, YafcLanguage};
+    use crate::{simplifier::Simplifier, simplify_str};
+
+    #[test]
+    fn negative_base_keeps_parentheses() {
+        assert_eq!(simplify_str("(-1)^n").unwrap(), "(-1) ^ n");
+        assert_eq!(simplify_str("(-2)^n").unwrap(), "(-2) ^ n");
+    }
+
+    #[test]
+    fn subtraction_and_division_associate_left() {
+        assert_eq!(simplify_str("a - b - c").unwrap(), "a - b - c");
+        assert_eq!(simplify_str("a - (b + c)").unwrap(), "a - b - c");
+        assert_eq!(simplify_str("x - (2*y + 3)").unwrap(), "x - 2 * y - 3");
+        let parsed = |s| format!("{:#}", YafcExpr::parse_infix(s).unwrap());
+        assert_eq!(parsed("a / b / c"), "a / b / c");
+        assert_eq!(parsed("a / (b / c)"), "a / (b / c)");
+    }
+
+    #[test]
+    fn radix_skips_fractions() {
+        let hex = |s| {
+            let expr = YafcExpr::parse_infix(s).unwrap();
+            format!("{:#}", expr.pretty().with_radix(Radix::Hex))
+        };
+        assert_eq!(hex("7/2"), "7 / 2");
+        assert_eq!(hex("x/2"), "x / 2");
+        assert_eq!(hex("255*x/2"), "0xff * x / 2");
+        assert_eq!(hex("255/x"), "0xff / x");
+    }
+
+    #[test]
+    fn binary_tree_folds_left() {
+        let expr = YafcExpr::parse_infix("a + (b + c) + max(x, max(y, z))").unwrap();
+        let folded = YafcExpr::parse_infix("((a + b) + c) + max(max(x, y), z)").unwrap();
+        // copied so that equal trees get the same node layout
+        let layout = |expr: &YafcExpr| expr.subexpr(expr.root.unwrap());
+        assert_eq!(layout(&expr.to_binary_tree()), layout(&folded));
+        assert_eq!(layout(&folded.to_binary_tree()), layout(&folded));
+        assert_eq!(expr.to_binary_tree().as_sum().len(), 4);
+    }
+
+    #[test]
+    fn even_and_odd_powers_of_minus_one() {
+        assert_eq!(simplify_str("(-1)^(2*n)").unwrap(), "1");
+        assert_eq!(simplify_str("(-1)^(2*n + 1)").unwrap(), "-1");
+        assert_eq!(simplify_str("(-1)^4").unwrap(), "1");
+        assert_eq!(simplify_str("(-1)^5").unwrap(), "-1");
+    }
+
+    #[test]
+    fn coefficient_before_bars() {
+        let parsed = |s| YafcExpr::parse_infix(s).map(|expr| format!("{expr:#}"));
+        assert_eq!(parsed("2|x|").unwrap(), "2 * |x|");
+        assert_eq!(parsed("2|x| + 3|y|").unwrap(), "2 * |x| + 3 * |y|");
+        assert_eq!(parsed("2|x|^2").unwrap(), "2 * |x| ^ 2");
+        assert_eq!(parsed("|(2|x|)|").unwrap(), "|2 * |x||");
+        // between bars a bar always closes them
+        assert!(parsed("|2|x||").is_err());
+        assert_eq!(simplify_str("2|-3|").unwrap(), "6");
+    }
+
+    #[test]
+    fn mixed_numbers() {
+        assert_eq!(simplify_str("1 1/2").unwrap(), "3 / 2");
+        assert_eq!(simplify_str("1  1/2").unwrap(), "1 / 2");
+        assert_eq!(simplify_str("1\n1/2").unwrap(), "1 / 2");
+        let parsed = format!("{:#}", YafcExpr::parse_infix("x^(2 1/2)").unwrap());
+        assert_eq!(parsed, "x ^ (2 + 1 / 2)");
+        assert!(YafcExpr::parse_infix("x^2 1/2").is_err());
+        assert!(YafcExpr::parse_infix("x**-2 1/2").is_err());
+    }
+
+    #[test]
+    fn fraction_styles() {
+        let styled = |s, fraction| {
+            let expr = Simplifier::run(&YafcExpr::parse_infix(s).unwrap());
+            format!("{:#}", expr.pretty().with_fraction_style(fraction))
+        };
+        assert_eq!(styled("7/2", FractionStyle::Improper), "7 / 2");
+        assert_eq!(styled("7/2", FractionStyle::Mixed), "3 1/2");
+        assert_eq!(styled("-7/2", FractionStyle::Improper), "-7 / 2");
+        assert_eq!(styled("-7/2", FractionStyle::Mixed), "-3 1/2");
+        assert_eq!(styled("1/2", FractionStyle::Mixed), "1 / 2");
+        assert_eq!(styled("x/2", FractionStyle::Mixed), "x / 2");
+    }
+
+    #[test]
+    fn long_flat_expressions() {
+        for op in [" + ", " * ", " - ", " / "] {
+            let terms: Vec<String> = (0..10_000).map(|n| format!("x{n}")).collect();
+            let input = terms.join(op);
+            let expr = YafcExpr::parse_infix(&input).unwrap();
+            assert_eq!(format!("{expr:#}"), input);
+            assert_eq!(format!("{expr}"), input.replace(' ', ""));
+            assert_eq!(
+                expr.format_truncated(20),
+                "x0 + x1 + x2 + x3…".replace(" + ", op)
+            );
+            assert!(expr.to_dot().len() > input.len());
+        }
+    }
+
+    #[test]
+    fn unary_plus_is_a_no_op() {
+        let parsed = |s| format!("{:#}", YafcExpr::parse_infix(s).unwrap());
+        assert_eq!(parsed("+x"), "x");
+        assert_eq!(parsed("+(a+b)"), "a + b");
+        assert_eq!(parsed("a + +b"), "a + b");
+        assert_eq!(parsed("- -x"), "- -x");
+        assert_eq!(simplify_str("- -x").unwrap(), "x");
+    }
+
+    #[test]
+    fn hashconsing_shares_equal_terms() {
+        let expr = YafcExpr::parse_infix("sin(x) + sin(x)")
+            .unwrap()
+            .hashconsed();
+        let root = expr.root.unwrap();
+        assert!(matches!(expr[root], YafcLanguage::Add([lhs, rhs]) if lhs == rhs));
+
+        let terms: Vec<String> = (0..1000).map(|n| format!("sin(x{})", n % 10)).collect();
+        let expr = YafcExpr::parse_infix(&terms.join(" + ")).unwrap();
+        let shared = expr.hashconsed();
+        assert_eq!(expr.as_ref().len(), 2999);
+        // 10 variables, 10 sines and the 999 additions
+        assert_eq!(shared.as_ref().len(), 1019);
+        assert_eq!(format!("{shared}"), format!("{expr}"));
+    }
+
+    #[test]
+    fn decimal_literals() {
+        let parsed = |s| format!("{:#}", YafcExpr::parse_infix(s).unwrap());
+        assert_eq!(parsed("0.5"), "0.5");
+        assert_eq!(parsed("x - 1.25"), "x - 1.25");
+        assert_eq!(parsed("2.0 * x"), "2.0 * x");
+        assert!(YafcExpr::parse_infix("1.").is_err());
+
+        let exact = |s: &str| s.parse::<super::Float>().unwrap().to_rational();
+        assert_eq!(exact("0.1"), Some(super::Rational::new(1, 10)));
+        assert_eq!(exact("2.0"), Some(super::Rational::from_integer(2)));
+        assert_eq!(exact("100000000000000000000.0"), None);
+    }
+
+    #[test]
+    fn dot_labels_and_edges() {
+        let dot = YafcExpr::parse_infix("x - 2").unwrap().to_dot();
+        for label in [
+            "label=\"x\"",
+            "label=\"2\"",
+            "label=\"-1\"",
+            "label=\"*\"",
+            "label=\"+\"",
+        ] {
+            assert!(dot.contains(label), "{label} in {dot}");
+        }
+        assert_eq!(dot.matches("->").count(), 4);
+        assert!(dot.starts_with("digraph {\n    ordering=out;\n"));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{
+    ast::{YafcExpr, YafcLanguage},
+    eval::{double_factorial, factorial, truth, AngleMode, EvalOptions},
+};
+use egg::{Id, Symbol};
+
+//
+
+/// An instruction of a [`Program`], the operands are popped from the stack
+/// and the result is pushed back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Push(f64),
+
+    /// The value of the variable at this index of [`Program::vars`]
+    Load(usize),
+
+    Add,
+    Mul,
+    Pow,
+    Max,
+    Min,
+    /// Base below the argument
+    Log,
+
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+
+    Fac,
+    DoubleFac,
+    Floor,
+    Ceil,
+    Abs,
+    Sin,
+    Cos,
+    Tan,
+
+    /// Degrees to radians, before the trigonometric functions in [`AngleMode::Degrees`]
+    Radians,
+}
+
+/// A stack machine program, see [`YafcExpr::to_bytecode`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    ops: Vec<Op>,
+    vars: Vec<Symbol>,
+    max_stack: usize,
+}
+
+impl YafcExpr {
+    pub fn to_bytecode(&self) -> Option<Program> {
+        self.to_bytecode_opt(<_>::default())
+    }
+
+    /// Compile the expression into a [`Program`] for evaluating it many times
+    ///
+    /// Returns `None` for sums, piecewise expressions and what can't be evaluated at all,
+    /// like calls
+    pub fn to_bytecode_opt(&self, options: EvalOptions) -> Option<Program> {
+        let mut program = Program {
+            ops: vec![],
+            vars: vec![],
+            max_stack: 0,
+        };
+        program.emit(self, self.root.expect("Not evaluated"), options, 0)?;
+        Some(program)
+    }
+}
+
+impl Program {
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// The variables in the order [`Program::run`] takes their values,
+    /// the order they first appear in the expression
+    pub fn vars(&self) -> impl Iterator<Item = &str> + '_ {
+        self.vars.iter().map(|var| var.as_str())
+    }
+
+    /// Run the program with the values of [`Program::vars`]
+    ///
+    /// Like [`YafcExpr::compile`], evaluations that fail give NaN.
+    /// Panics if there are fewer values than variables
+    pub fn run(&self, args: &[f64]) -> f64 {
+        let mut stack = Vec::with_capacity(self.max_stack);
+        for op in &self.ops {
+            let value = match *op {
+                Op::Push(value) => value,
+                Op::Load(n) => args[n],
+                op if op.arity() == 2 => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    op.apply_bin(a, b)
+                }
+                op => {
+                    let a = stack.pop().unwrap();
+                    op.apply_un(a)
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().unwrap()
+    }
+
+    /// Append the ops of the node `i`, `depth` is the stack size before them
+    fn emit(&mut self, src: &YafcExpr, i: Id, options: EvalOptions, depth: usize) -> Option<()> {
+        use YafcLanguage::*;
+
+        let op = match &src[i] {
+            _ if src.eval_preferred(i, options).is_some() => {
+                Op::Push(src.eval_preferred(i, options).unwrap())
+            }
+            Num(num) => Op::Push(*num as f64),
+            Float(float) => Op::Push(float.0),
+            Euler => Op::Push(std::f64::consts::E),
+            Var(var) | Const(var) => {
+                let n = match self.vars.iter().position(|other| other == var) {
+                    Some(n) => n,
+                    None => {
+                        self.vars.push(*var);
+                        self.vars.len() - 1
+                    }
+                };
+                Op::Load(n)
+            }
+            Opaque(a) => return self.emit(src, *a, options, depth),
+            Add([a, b]) | Mul([a, b]) | Pow([a, b]) | Max([a, b]) | Min([a, b]) | Log([a, b])
+            | Eq([a, b]) | Lt([a, b]) | Le([a, b]) | Gt([a, b]) | Ge([a, b]) | And([a, b])
+            | Or([a, b]) => {
+                self.emit(src, *a, options, depth)?;
+                self.emit(src, *b, options, depth + 1)?;
+                match &src[i] {
+                    Add(_) => Op::Add,
+                    Mul(_) => Op::Mul,
+                    Pow(_) => Op::Pow,
+                    Max(_) => Op::Max,
+                    Min(_) => Op::Min,
+                    Log(_) => Op::Log,
+                    Eq(_) => Op::Eq,
+                    Lt(_) => Op::Lt,
+                    Le(_) => Op::Le,
+                    Gt(_) => Op::Gt,
+                    Ge(_) => Op::Ge,
+                    And(_) => Op::And,
+                    _ => Op::Or,
+                }
+            }
+            Fac(a) | DoubleFac(a) | Floor(a) | Ceil(a) | Abs(a) | Sin(a) | Cos(a) | Tan(a) => {
+                self.emit(src, *a, options, depth)?;
+                match &src[i] {
+                    Fac(_) => Op::Fac,
+                    DoubleFac(_) => Op::DoubleFac,
+                    Floor(_) => Op::Floor,
+                    Ceil(_) => Op::Ceil,
+                    Abs(_) => Op::Abs,
+                    trig => {
+                        if options.angle == AngleMode::Degrees {
+                            self.ops.push(Op::Radians);
+                        }
+                        match trig {
+                            Sin(_) => Op::Sin,
+                            Cos(_) => Op::Cos,
+                            _ => Op::Tan,
+                        }
+                    }
+                }
+            }
+            // the loops and branches would need jumps
+            Sum(_) | Piecewise(_) | Call(..) | Imag | Matrix(_) | Row(_) => return None,
+        };
+        self.ops.push(op);
+        self.max_stack = self.max_stack.max(depth + 1);
+        Some(())
+    }
+}
+
+impl Op {
+    fn arity(self) -> usize {
+        match self {
+            Op::Push(_) | Op::Load(_) => 0,
+            Op::Add
+            | Op::Mul
+            | Op::Pow
+            | Op::Max
+            | Op::Min
+            | Op::Log
+            | Op::Eq
+            | Op::Lt
+            | Op::Le
+            | Op::Gt
+            | Op::Ge
+            | Op::And
+            | Op::Or => 2,
+            _ => 1,
+        }
+    }
+
+    /// A failed operand fails the whole operation, like in [`YafcExpr::compile`]
+    fn apply_bin(self, a: f64, b: f64) -> f64 {
+        if a.is_nan() || b.is_nan() {
+            return f64::NAN;
+        }
+        match self {
+            Op::Add => a + b,
+            Op::Mul => a * b,
+            Op::Pow => a.powf(b),
+            Op::Max => a.max(b),
+            Op::Min => a.min(b),
+            Op::Log => b.ln() / a.ln(),
+            Op::Eq => truth(a == b),
+            Op::Lt => truth(a < b),
+            Op::Le => truth(a <= b),
+            Op::Gt => truth(a > b),
+            Op::Ge => truth(a >= b),
+            Op::And => truth(a != 0.0 && b != 0.0),
+            Op::Or => truth(a != 0.0 || b != 0.0),
+            _ => unreachable!(),
+        }
+    }
+
+    fn apply_un(self, a: f64) -> f64 {
+        match self {
+            Op::Fac => factorial(a).unwrap_or(f64::NAN),
+            Op::DoubleFac => double_factorial(a).unwrap_or(f64::NAN),
+            Op::Floor => a.floor(),
+            Op::Ceil => a.ceil(),
+            Op::Abs => a.abs(),
+            Op::Sin => a.sin(),
+            Op::Cos => a.cos(),
+            Op::Tan => a.tan(),
+            Op::Radians => a.to_radians(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::Op;
+    use crate::ast::YafcExpr;
+    use std::collections::HashMap;
+
+    #[test]
+    fn programs_match_eval() {
+        let inputs = [
+            "x^2 + 3*x*y - 1/y",
+            "sin(x) * cos(y) + max(x, y)",
+            "floor(x * 10) / 10 + |y - x|",
+            "log(2, y) + 4! - x^0.5",
+            "x < y and y <= 3",
+        ];
+        for (x, y) in [(0.5, 2.0), (3.0, 0.25), (1.0, 1.0)] {
+            let vars = HashMap::from([("x", x), ("y", y)]);
+            for input in inputs {
+                let expr = YafcExpr::parse_infix(input).unwrap();
+                let program = expr.to_bytecode().unwrap();
+                let args: Vec<f64> = program.vars().map(|var| vars[var]).collect();
+                let expected = expr.eval_f64(&vars).unwrap();
+                assert!(
+                    (program.run(&args) - expected).abs() < 1e-12,
+                    "{input} at x={x}, y={y}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn variables_are_loaded_in_order() {
+        let program = YafcExpr::parse_infix("y * x + y")
+            .unwrap()
+            .to_bytecode()
+            .unwrap();
+        assert_eq!(program.vars().collect::<Vec<_>>(), ["y", "x"]);
+        assert_eq!(
+            program.ops(),
+            [Op::Load(0), Op::Load(1), Op::Mul, Op::Load(0), Op::Add]
+        );
+        assert_eq!(program.run(&[2.0, 5.0]), 12.0);
+    }
+}
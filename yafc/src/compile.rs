@@ -0,0 +1,205 @@
+use crate::{
+    ast::{YafcExpr, YafcLanguage},
+    eval::{double_factorial, factorial, truth, AngleMode, EvalOptions, MAX_SUM_ITERATIONS},
+};
+use egg::{Id, Symbol};
+
+//
+
+/// A compiled subexpression, called with the arguments and the values of the sum indices
+type Code = Box<dyn Fn(&[f64], &mut [f64]) -> f64 + Send + Sync>;
+
+impl YafcExpr {
+    pub fn compile(&self, vars: &[&str]) -> Option<impl Fn(&[f64]) -> f64 + Send + Sync> {
+        self.compile_opt(vars, <_>::default())
+    }
+
+    /// Turn the expression into a closure that takes the values of `vars` in that order,
+    /// for evaluating it many times without the map lookups of [`YafcExpr::eval_f64`]
+    ///
+    /// Returns `None` for other variables and for what can't be evaluated at all, like calls.
+    /// Evaluations that fail, like `(-1)!` or sums with more than [`MAX_SUM_ITERATIONS`]
+    /// terms, give NaN. The closure panics if it gets fewer values than there are `vars`
+    pub fn compile_opt(
+        &self,
+        vars: &[&str],
+        options: EvalOptions,
+    ) -> Option<impl Fn(&[f64]) -> f64 + Send + Sync> {
+        let mut compiler = Compiler {
+            src: self,
+            vars,
+            options,
+            indices: vec![],
+            locals: 0,
+        };
+        let code = compiler.compile(self.root.expect("Not evaluated"))?;
+        let locals = compiler.locals;
+        Some(move |args: &[f64]| code(args, &mut vec![0.0; locals]))
+    }
+}
+
+//
+
+struct Compiler<'a> {
+    src: &'a YafcExpr,
+    vars: &'a [&'a str],
+    options: EvalOptions,
+    /// Sum indices in scope and their slots, the innermost last
+    indices: Vec<(Symbol, usize)>,
+    /// Number of slots for the sum indices, the sums nested the deepest need the most
+    locals: usize,
+}
+
+impl Compiler<'_> {
+    fn compile(&mut self, i: Id) -> Option<Code> {
+        use YafcLanguage::*;
+
+        let degrees = self.options.angle == AngleMode::Degrees;
+        let angle = move |v: f64| if degrees { v.to_radians() } else { v };
+
+        if let Some(value) = self.src.eval_preferred(i, self.options) {
+            return Some(Box::new(move |_, _| value));
+        }
+
+        Some(match &self.src[i] {
+            Num(num) => {
+                let num = *num as f64;
+                Box::new(move |_, _| num)
+            }
+            Float(float) => {
+                let float = float.0;
+                Box::new(move |_, _| float)
+            }
+            Euler => Box::new(|_, _| std::f64::consts::E),
+            Var(var) | Const(var) => {
+                match self.indices.iter().rev().find(|(index, _)| index == var) {
+                    Some(&(_, slot)) => Box::new(move |_, locals| locals[slot]),
+                    None => {
+                        let n = self.vars.iter().position(|other| *other == var.as_str())?;
+                        Box::new(move |args, _| args[n])
+                    }
+                }
+            }
+            Add(ab) => self.bin(*ab, |a, b| a + b)?,
+            Mul(ab) => self.bin(*ab, |a, b| a * b)?,
+            Pow(ab) => self.bin(*ab, f64::powf)?,
+            Max(ab) => self.bin(*ab, f64::max)?,
+            Min(ab) => self.bin(*ab, f64::min)?,
+            Log(ab) => self.bin(*ab, |a, b| b.ln() / a.ln())?,
+            Eq(ab) => self.bin(*ab, |a, b| truth(a == b))?,
+            Lt(ab) => self.bin(*ab, |a, b| truth(a < b))?,
+            Le(ab) => self.bin(*ab, |a, b| truth(a <= b))?,
+            Gt(ab) => self.bin(*ab, |a, b| truth(a > b))?,
+            Ge(ab) => self.bin(*ab, |a, b| truth(a >= b))?,
+            And(ab) => self.bin(*ab, |a, b| truth(a != 0.0 && b != 0.0))?,
+            Or(ab) => self.bin(*ab, |a, b| truth(a != 0.0 || b != 0.0))?,
+            Fac(a) => self.un(*a, |n| factorial(n).unwrap_or(f64::NAN))?,
+            DoubleFac(a) => self.un(*a, |n| double_factorial(n).unwrap_or(f64::NAN))?,
+            Floor(a) => self.un(*a, f64::floor)?,
+            Ceil(a) => self.un(*a, f64::ceil)?,
+            Sin(a) => self.un(*a, move |v| angle(v).sin())?,
+            Cos(a) => self.un(*a, move |v| angle(v).cos())?,
+            Tan(a) => self.un(*a, move |v| angle(v).tan())?,
+            Abs(a) => self.un(*a, f64::abs)?,
+            Opaque(a) => self.compile(*a)?,
+            Sum([index, from, to, body]) => {
+                let Var(index) = self.src[*index] else {
+                    return None;
+                };
+                let from = self.compile(*from)?;
+                let to = self.compile(*to)?;
+
+                let slot = self.indices.len();
+                self.locals = self.locals.max(slot + 1);
+                self.indices.push((index, slot));
+                let body = self.compile(*body);
+                self.indices.pop();
+                let body = body?;
+
+                Box::new(move |args, locals| {
+                    let (from, to) = (from(args, locals), to(args, locals));
+                    if from.is_nan() || to.is_nan() {
+                        return f64::NAN;
+                    }
+                    let (from, to) = (from.ceil() as i64, to.floor() as i64);
+                    if to.saturating_sub(from) >= MAX_SUM_ITERATIONS {
+                        return f64::NAN;
+                    }
+                    let mut sum = 0.0;
+                    for k in from..=to {
+                        locals[slot] = k as f64;
+                        sum += body(args, locals);
+                    }
+                    sum
+                })
+            }
+            // the first branch that holds, NaN if none does
+            Piecewise(args) => {
+                let branches = args
+                    .chunks(2)
+                    .map(|branch| Some((self.compile(branch[0])?, self.compile(branch[1])?)))
+                    .collect::<Option<Vec<_>>>()?;
+                Box::new(move |args, locals| {
+                    for (cond, value) in &branches {
+                        match cond(args, locals) {
+                            cond if cond.is_nan() => return f64::NAN,
+                            cond if cond != 0.0 => return value(args, locals),
+                            _ => {}
+                        }
+                    }
+                    f64::NAN
+                })
+            }
+            // expand user defined functions first, and there are no complex numbers
+            Call(..) | Imag | Matrix(_) | Row(_) => return None,
+        })
+    }
+
+    /// A failed operand fails the whole operation, even `max(NaN, 1)`
+    fn bin(&mut self, [a, b]: [Id; 2], f: fn(f64, f64) -> f64) -> Option<Code> {
+        let (a, b) = (self.compile(a)?, self.compile(b)?);
+        Some(Box::new(move |args, locals| {
+            let (a, b) = (a(args, locals), b(args, locals));
+            if a.is_nan() || b.is_nan() {
+                f64::NAN
+            } else {
+                f(a, b)
+            }
+        }))
+    }
+
+    fn un(&mut self, a: Id, f: impl Fn(f64) -> f64 + Send + Sync + 'static) -> Option<Code> {
+        let a = self.compile(a)?;
+        Some(Box::new(move |args, locals| f(a(args, locals))))
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::YafcExpr;
+
+    #[test]
+    fn compiled_closure_takes_positional_values() {
+        let expr = YafcExpr::parse_infix("x^2 + y").unwrap();
+        let compiled = expr.compile(&["x", "y"]).unwrap();
+        assert_eq!(compiled(&[0.0, 0.0]), 0.0);
+        assert_eq!(compiled(&[2.0, 1.0]), 5.0);
+        assert_eq!(compiled(&[-3.0, 0.5]), 9.5);
+        assert!(expr.compile(&["x"]).is_none());
+        assert!(YafcExpr::parse_infix("(-1)!")
+            .unwrap()
+            .compile(&[])
+            .unwrap()(&[])
+        .is_nan());
+    }
+
+    #[test]
+    fn long_sums_give_nan() {
+        let expr = YafcExpr::parse_infix("sum(k, 1, n, k)").unwrap();
+        let compiled = expr.compile(&["n"]).unwrap();
+        assert_eq!(compiled(&[100.0]), 5050.0);
+        assert!(compiled(&[1e12]).is_nan());
+    }
+}
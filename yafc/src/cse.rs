@@ -0,0 +1,113 @@
+use crate::ast::{YafcExpr, YafcLanguage};
+use egg::{Id, Language};
+use std::collections::HashMap;
+
+//
+
+/// Subexpressions pulled out by [`YafcExpr::cse`], by the name that replaces them
+pub type Bindings = Vec<(String, YafcExpr)>;
+
+impl YafcExpr {
+    /// Common subexpression elimination
+    ///
+    /// Every subexpression that appears more than once is bound to a name like `$0`,
+    /// which can't collide with parsed variables. `(x+1)*(x+1)` gives `$0 = x+1`
+    /// and `$0*$0`. Bindings only refer to the ones before them, so they can be
+    /// evaluated in order.
+    ///
+    /// Subexpressions with a sum index variable are left in place
+    pub fn cse(&self) -> (Bindings, YafcExpr) {
+        let dag = self.hashconsed();
+        let nodes = dag.as_ref();
+
+        let mut uses = vec![0usize; nodes.len()];
+        let mut indices = vec![];
+        for node in nodes {
+            for &child in node.children() {
+                uses[usize::from(child)] += 1;
+            }
+            if let YafcLanguage::Sum([index, ..]) = node {
+                indices.push(*index);
+            }
+        }
+
+        // ids of `dag` are in post order, so bindings come before their uses
+        let mut names = HashMap::new();
+        let mut order = vec![];
+        for (i, node) in nodes.iter().enumerate() {
+            let i = Id::from(i);
+            let bound = indices.iter().any(|&index| {
+                let YafcLanguage::Var(index) = &dag[index] else {
+                    return false;
+                };
+                dag.contains_var_at(i, index.as_str())
+            });
+            if uses[usize::from(i)] > 1 && !node.is_leaf() && !bound {
+                names.insert(i, format!("${}", names.len()));
+                order.push(i);
+            }
+        }
+
+        let bindings = order
+            .into_iter()
+            .map(|i| (names[&i].clone(), dag.rebuild_cse(i, &names)))
+            .collect();
+        let expr = dag.rebuild_cse(dag.root.expect("Not evaluated"), &names);
+        (bindings, expr)
+    }
+
+    /// Copy of the subexpression `i` with the named subexpressions below it replaced
+    fn rebuild_cse(&self, i: Id, names: &HashMap<Id, String>) -> YafcExpr {
+        fn rec(src: &YafcExpr, i: Id, names: &HashMap<Id, String>, out: &mut YafcExpr) -> Id {
+            match names.get(&i) {
+                Some(name) => out.make_var(name),
+                None => {
+                    let node = src[i]
+                        .clone()
+                        .map_children(|child| rec(src, child, names, out));
+                    out.add(node)
+                }
+            }
+        }
+
+        let mut out = YafcExpr::new();
+        let node = self[i]
+            .clone()
+            .map_children(|child| rec(self, child, names, &mut out));
+        out.root = Some(out.add(node));
+        out
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::YafcExpr;
+
+    fn cse(s: &str) -> (Vec<String>, String) {
+        let (bindings, expr) = YafcExpr::parse_infix(s).unwrap().cse();
+        let bindings = bindings
+            .into_iter()
+            .map(|(name, value)| format!("{name} = {value}"))
+            .collect();
+        (bindings, format!("{expr}"))
+    }
+
+    #[test]
+    fn repeated_subexpressions_are_bound() {
+        let (bindings, expr) = cse("(x+1)*(x+1)");
+        assert_eq!(bindings, ["$0 = x+1"]);
+        assert_eq!(expr, "$0*$0");
+
+        let (bindings, _) = cse("(x+1)*(y+2)");
+        assert!(bindings.is_empty());
+    }
+
+    #[test]
+    fn bindings_refer_to_earlier_ones() {
+        let (bindings, expr) = cse("sin(x+1)*sin(x+1) + (x+1)");
+        assert_eq!(bindings, ["$0 = x+1", "$1 = sin($0)"]);
+        assert_eq!(expr, "$1*$1+$0");
+    }
+}
@@ -0,0 +1,210 @@
+use crate::ast::{BinOp, YafcExpr, YafcLanguage};
+use egg::{Id, Language, Symbol};
+use lalrpop_util::{lexer::Token, ParseError};
+use std::{collections::HashMap, fmt};
+
+//
+
+/// User defined functions like `f(x) = x^2 + 1`, by name and arity
+#[derive(Debug, Clone, Default)]
+pub struct Definitions {
+    functions: HashMap<(Symbol, usize), Function>,
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    params: Vec<Symbol>,
+    body: YafcExpr,
+}
+
+/// Recursive definitions like `f(x) = f(x + 1)` stop expanding here
+const MAX_DEPTH: usize = 64;
+
+/// Definitions like `h(x) = h(x) + h(x)` double with every expansion,
+/// they fail at this many nodes instead of at [`MAX_DEPTH`]
+const MAX_NODES: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpandError {
+    /// The expanded expression grew past [`MAX_NODES`]
+    TooLarge,
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpandError::TooLarge => write!(f, "The expanded definitions are too large"),
+        }
+    }
+}
+
+impl std::error::Error for ExpandError {}
+
+impl Definitions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define `name(params..) = body`, replacing any earlier definition with the same arity
+    pub fn define(&mut self, name: &str, params: &[&str], body: YafcExpr) {
+        let params: Vec<Symbol> = params.iter().map(|&param| param.into()).collect();
+        self.functions
+            .insert((name.into(), params.len()), Function { params, body });
+    }
+
+    /// [`YafcExpr::parse_infix`] that calls the defined functions: `f(x + 1)` is a call
+    /// if `f` is defined, otherwise it's `f * (x + 1)`
+    ///
+    /// Equations like `f(x) = x^2 + 1` keep the call on the left side, see
+    /// [`Definitions::define_eq`]
+    pub fn parse<'a>(&self, s: &'a str) -> Result<YafcExpr, ParseError<usize, Token<'a>, String>> {
+        let expr = YafcExpr::parse_calls(s)?;
+        let defined = |name: &str| self.functions.contains_key(&(name.into(), 1));
+        match Self::as_definition(&expr) {
+            // the function can call itself, but `x(x + 1)` with the parameter `x` is a product
+            Some((function, params, _)) => Ok(expr.calls_to_products(|name| {
+                let name = Symbol::from(name);
+                !params.contains(&name)
+                    && ((name == function && params.len() == 1) || defined(name.as_str()))
+            })),
+            None => Ok(expr.calls_to_products(defined)),
+        }
+    }
+
+    /// Define a function from an equation like `f(x, y) = x * y`
+    ///
+    /// Returns `false` if the left side isn't a call with distinct variables
+    pub fn define_eq(&mut self, definition: &YafcExpr) -> bool {
+        let Some((name, params, body)) = Self::as_definition(definition) else {
+            return false;
+        };
+        let params: Vec<&str> = params.iter().map(|param| param.as_str()).collect();
+        self.define(name.as_str(), &params, body);
+        true
+    }
+
+    /// The name, the parameters and the body of an equation like `f(x, y) = x * y`
+    fn as_definition(definition: &YafcExpr) -> Option<(Symbol, Vec<Symbol>, YafcExpr)> {
+        let (_, lhs, body) = definition
+            .as_relation()
+            .filter(|(op, ..)| *op == BinOp::Eq)?;
+        let YafcLanguage::Call(name, args) = &lhs[lhs.root.expect("Not evaluated")] else {
+            return None;
+        };
+
+        let mut params = vec![];
+        for &arg in args {
+            match lhs[arg] {
+                YafcLanguage::Var(param) if !params.contains(&param) => params.push(param),
+                _ => return None,
+            }
+        }
+        Some((*name, params, body))
+    }
+
+    /// Replace calls to defined functions with their bodies
+    ///
+    /// Calls to undefined functions, or with the wrong number of arguments, are left alone
+    pub fn expand(&self, expr: &YafcExpr) -> Result<YafcExpr, ExpandError> {
+        let mut out = YafcExpr::new();
+        let root = self.expand_rec(expr, expr.root.expect("Not evaluated"), 0, &mut out)?;
+        out.root = Some(root);
+        Ok(out)
+    }
+
+    fn expand_rec(
+        &self,
+        src: &YafcExpr,
+        i: Id,
+        depth: usize,
+        out: &mut YafcExpr,
+    ) -> Result<Id, ExpandError> {
+        if out.as_ref().len() > MAX_NODES {
+            return Err(ExpandError::TooLarge);
+        }
+        let mut node = src[i].clone();
+        for child in node.children_mut() {
+            *child = self.expand_rec(src, *child, depth, out)?;
+        }
+
+        if let YafcLanguage::Call(name, args) = &node {
+            if let Some(function) = self.functions.get(&(*name, args.len())) {
+                if depth < MAX_DEPTH {
+                    let args: Vec<_> = args.iter().map(|&arg| out.subexpr(arg)).collect();
+                    let body = function.apply(&args);
+                    return self.expand_rec(
+                        &body,
+                        body.root.expect("Not evaluated"),
+                        depth + 1,
+                        out,
+                    );
+                }
+            }
+        }
+        Ok(out.add(node))
+    }
+}
+
+impl Function {
+    fn apply(&self, args: &[YafcExpr]) -> YafcExpr {
+        // rename the parameters first, so that `f(y, x)` with `f(x, y)`
+        // doesn't substitute into the arguments
+        let placeholder = |n: usize| format!("$arg{n}");
+        let body = self
+            .params
+            .iter()
+            .enumerate()
+            .fold(self.body.clone(), |body, (n, param)| {
+                body.substitute(param.as_str(), &YafcExpr::var(&placeholder(n)))
+            });
+        args.iter()
+            .enumerate()
+            .fold(body, |body, (n, arg)| body.substitute(&placeholder(n), arg))
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::{Definitions, ExpandError};
+    use crate::{ast::YafcExpr, simplifier::Simplifier};
+
+    fn simplify(defs: &Definitions, s: &str) -> String {
+        format!(
+            "{:#}",
+            Simplifier::run(&defs.expand(&defs.parse(s).unwrap()).unwrap())
+        )
+    }
+
+    #[test]
+    fn defined_function_is_called() {
+        let mut defs = Definitions::new();
+        assert!(defs.define_eq(&defs.parse("f(x) = x^2 + 1").unwrap()));
+        assert_eq!(simplify(&defs, "f(3)"), "10");
+        assert_eq!(simplify(&defs, "g(3)"), "3 * g");
+    }
+
+    #[test]
+    fn single_letter_call_is_a_product() {
+        let product = |s| format!("{:#}", YafcExpr::parse_infix(s).unwrap());
+        assert_eq!(product("x(x+1)"), "x * (x + 1)");
+        assert_eq!(product("2x(x+1)"), "2 * x * (x + 1)");
+        assert_eq!(product("foo(x)"), "foo(x)");
+    }
+
+    #[test]
+    fn body_calls_to_parameters_are_products() {
+        let mut defs = Definitions::new();
+        assert!(defs.define_eq(&defs.parse("f(x) = x(x+1)").unwrap()));
+        assert_eq!(simplify(&defs, "f(2)"), "6");
+    }
+
+    #[test]
+    fn branching_definition_is_too_large() {
+        let mut defs = Definitions::new();
+        assert!(defs.define_eq(&defs.parse("h(x) = h(x) + h(x)").unwrap()));
+        let call = defs.parse("h(1)").unwrap();
+        assert_eq!(defs.expand(&call).unwrap_err(), ExpandError::TooLarge);
+    }
+}
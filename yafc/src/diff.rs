@@ -0,0 +1,236 @@
+use crate::{
+    ast::{BinOp, YafcExpr, YafcLanguage},
+    simplifier::Simplifier,
+};
+use egg::{Id, Language};
+use std::collections::HashMap;
+
+//
+
+impl YafcExpr {
+    /// Simplified derivative of the expression with respect to `var`
+    ///
+    /// The trigonometric functions are in radians.
+    /// Returns `None` for expressions that can't be differentiated yet, like `floor(x)`
+    pub fn differentiate(&self, var: &str) -> Option<YafcExpr> {
+        // equal subexpressions share an id, so each is differentiated once
+        let src = self.hashconsed();
+        let mut differentiator = Differentiator::new(&src, var);
+        let root = differentiator.diff(src.root.expect("Not evaluated"))?;
+
+        let mut out = differentiator.out;
+        out.root = Some(root);
+        Some(Simplifier::run(&out))
+    }
+}
+
+//
+
+struct Differentiator<'a> {
+    src: &'a YafcExpr,
+    /// Whether the node at each index has the variable in it
+    has_var: Vec<bool>,
+    out: YafcExpr,
+    memo: HashMap<Id, Option<Id>>,
+    /// How many nodes were differentiated without the memo
+    #[cfg(test)]
+    visits: usize,
+}
+
+impl<'a> Differentiator<'a> {
+    fn new(src: &'a YafcExpr, var: &str) -> Self {
+        // the children come before their parents
+        let mut has_var = Vec::with_capacity(src.as_ref().len());
+        for node in src.as_ref() {
+            let found = match node {
+                YafcLanguage::Var(v) => v.as_str() == var,
+                node => node
+                    .children()
+                    .iter()
+                    .any(|&child| has_var[usize::from(child)]),
+            };
+            has_var.push(found);
+        }
+        Self {
+            src,
+            has_var,
+            out: YafcExpr::new(),
+            memo: HashMap::new(),
+            #[cfg(test)]
+            visits: 0,
+        }
+    }
+
+    fn diff(&mut self, i: Id) -> Option<Id> {
+        if let Some(result) = self.memo.get(&i) {
+            return *result;
+        }
+        let result = self.diff_uncached(i);
+        self.memo.insert(i, result);
+        result
+    }
+
+    fn diff_uncached(&mut self, i: Id) -> Option<Id> {
+        #[cfg(test)]
+        {
+            self.visits += 1;
+        }
+        if !self.has_var(i) {
+            return Some(self.out.make_num(0));
+        }
+
+        Some(match &self.src[i] {
+            YafcLanguage::Var(_) => self.out.make_num(1),
+            YafcLanguage::Add([lhs, rhs]) => {
+                let lhs = self.diff(*lhs)?;
+                let rhs = self.diff(*rhs)?;
+                self.out.make_add(lhs, rhs)
+            }
+            YafcLanguage::Mul([lhs, rhs]) => {
+                // (fg)' = f'g + fg'
+                let d_lhs = self.diff(*lhs)?;
+                let d_rhs = self.diff(*rhs)?;
+                let lhs = self.copy(*lhs);
+                let rhs = self.copy(*rhs);
+                let a = self.out.make_mul(d_lhs, rhs);
+                let b = self.out.make_mul(lhs, d_rhs);
+                self.out.make_add(a, b)
+            }
+            YafcLanguage::Pow([base, exp]) if !self.has_var(*exp) => {
+                // (f^n)' = n * f^(n-1) * f'
+                let d_base = self.diff(*base)?;
+                let base = self.copy(*base);
+                let exp = self.copy(*exp);
+                let neg_1 = self.out.make_num(-1);
+                let exp_dec = self.out.make_add(exp, neg_1);
+                let pow = self.out.make_pow(base, exp_dec);
+                let scaled = self.out.make_mul(exp, pow);
+                self.out.make_mul(scaled, d_base)
+            }
+            YafcLanguage::Pow([base, exp]) => {
+                // (f^g)' = f^g * (g' * ln(f) + g * f' / f)
+                let d_base = self.diff(*base)?;
+                let d_exp = self.diff(*exp)?;
+                let pow = self.copy(i);
+                let base = self.copy(*base);
+                let exp = self.copy(*exp);
+                let ln = self.ln(base);
+                let a = self.out.make_mul(d_exp, ln);
+                let neg_1 = self.out.make_num(-1);
+                let recip = self.out.make_pow(base, neg_1);
+                let b = self.out.make_mul(exp, d_base);
+                let b = self.out.make_mul(b, recip);
+                let sum = self.out.make_add(a, b);
+                self.out.make_mul(pow, sum)
+            }
+            YafcLanguage::Log([base, arg]) if !self.has_var(*base) => {
+                // log(b, f)' = f' / (f * ln(b))
+                let d_arg = self.diff(*arg)?;
+                let base = self.copy(*base);
+                let arg = self.copy(*arg);
+                let ln = self.ln(base);
+                let denom = self.out.make_mul(arg, ln);
+                let neg_1 = self.out.make_num(-1);
+                let recip = self.out.make_pow(denom, neg_1);
+                self.out.make_mul(d_arg, recip)
+            }
+            YafcLanguage::Sin(arg) => {
+                let d_arg = self.diff(*arg)?;
+                let arg = self.copy(*arg);
+                let cos = self.out.add(YafcLanguage::Cos(arg));
+                self.out.make_mul(cos, d_arg)
+            }
+            YafcLanguage::Cos(arg) => {
+                let d_arg = self.diff(*arg)?;
+                let arg = self.copy(*arg);
+                let sin = self.out.add(YafcLanguage::Sin(arg));
+                let neg_1 = self.out.make_num(-1);
+                let neg_sin = self.out.make_mul(neg_1, sin);
+                self.out.make_mul(neg_sin, d_arg)
+            }
+            YafcLanguage::Tan(arg) => {
+                // tan' = 1 + tan^2
+                let d_arg = self.diff(*arg)?;
+                let tan = self.copy(i);
+                let two = self.out.make_num(2);
+                let square = self.out.make_pow(tan, two);
+                let one = self.out.make_num(1);
+                let sec_2 = self.out.make_add(one, square);
+                self.out.make_mul(sec_2, d_arg)
+            }
+            YafcLanguage::Sum([index, from, to, body])
+                if !self.has_var(*from) && !self.has_var(*to) && !self.has_var(*index) =>
+            {
+                let d_body = self.diff(*body)?;
+                let index = self.copy(*index);
+                let from = self.copy(*from);
+                let to = self.copy(*to);
+                self.out.add(YafcLanguage::Sum([index, from, to, d_body]))
+            }
+            _ => return None,
+        })
+    }
+
+    fn has_var(&self, i: Id) -> bool {
+        self.has_var[usize::from(i)]
+    }
+
+    /// `ln(i)` of an id in the output
+    fn ln(&mut self, i: Id) -> Id {
+        let e = self.out.add(YafcLanguage::Euler);
+        self.out.make_bin(BinOp::Log, e, i)
+    }
+
+    fn copy(&mut self, i: Id) -> Id {
+        self.out.copy_from(self.src, i)
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::Differentiator;
+    use crate::ast::YafcExpr;
+
+    fn derivative(s: &str) -> Option<String> {
+        let expr = YafcExpr::parse_infix(s).unwrap();
+        expr.differentiate("x").map(|d| format!("{d:#}"))
+    }
+
+    #[test]
+    fn trigonometric_functions() {
+        assert_eq!(derivative("x^3 + sin(x)").unwrap(), "3 * x ^ 2 + cos(x)");
+        assert_eq!(derivative("cos(2*x)").unwrap(), "-2 * sin(2 * x)");
+        assert_eq!(derivative("tan(x)").unwrap(), "tan(x) ^ 2 + 1");
+    }
+
+    #[test]
+    fn logarithms_and_variable_exponents() {
+        assert_eq!(derivative("ln(x)").unwrap(), "1 / x");
+        assert_eq!(derivative("log(2, x)").unwrap(), "1 / (x * ln(2))");
+        assert_eq!(derivative("exp(x)").unwrap(), "e ^ x");
+        assert_eq!(derivative("2^x").unwrap(), "2 ^ x * ln(2)");
+        assert_eq!(derivative("x^x").unwrap(), "x ^ x * (ln(x) + 1)");
+    }
+
+    #[test]
+    fn unsupported_functions() {
+        assert_eq!(derivative("floor(x)"), None);
+        assert_eq!(derivative("log(x, 2)"), None);
+    }
+
+    #[test]
+    fn repeated_subexpressions_are_differentiated_once() {
+        let visits = |s: &str| {
+            let src = YafcExpr::parse_infix(s).unwrap().hashconsed();
+            let mut differentiator = Differentiator::new(&src, "x");
+            differentiator.diff(src.root.unwrap()).unwrap();
+            differentiator.visits
+        };
+        let term = "sin(x^2 + 1)";
+        let sum = [term; 8].join(" + ");
+        // only the 7 additions are new
+        assert_eq!(visits(&sum), visits(term) + 7);
+    }
+}
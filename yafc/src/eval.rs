@@ -0,0 +1,250 @@
+use crate::{
+    ast::{Num, Rational, YafcExpr, YafcLanguage},
+    simplifier::fold_const,
+};
+use egg::Id;
+use std::collections::HashMap;
+
+//
+
+/// Sums with more terms than this fail to evaluate, `sum(k, 1, 10^12, k)` would take hours
+pub const MAX_SUM_ITERATIONS: i64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EvalOptions {
+    /// Unit of the arguments of `sin`, `cos` and `tan`
+    pub angle: AngleMode,
+
+    /// How the constant subexpressions are evaluated
+    pub prefer: NumberPreference,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AngleMode {
+    #[default]
+    Radians,
+    Degrees,
+}
+
+/// How numbers that could be exact or floating point are handled by the numeric evaluation
+/// of [`YafcExpr::eval_f64`], [`YafcExpr::compile`] and [`YafcExpr::to_bytecode`],
+/// and by the simplifier with [`SimplifyOptions::prefer`](crate::simplifier::SimplifyOptions::prefer)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberPreference {
+    /// Everything in floating point, `1/49*49 - 1` isn't quite `0`
+    #[default]
+    Float,
+
+    /// Subexpressions without variables are evaluated as exact rationals first and
+    /// rounded once, `1/49*49 - 1` is `0`
+    Rational,
+}
+
+impl YafcExpr {
+    pub fn eval_f64(&self, vars: &HashMap<&str, f64>) -> Option<f64> {
+        self.eval_f64_opt(vars, <_>::default())
+    }
+
+    /// Numerically evaluate the expression with the given variable values
+    ///
+    /// Returns `None` for unknown variables, for results that aren't finite,
+    /// like `1/0`, and for sums with more than [`MAX_SUM_ITERATIONS`] terms
+    pub fn eval_f64_opt(&self, vars: &HashMap<&str, f64>, options: EvalOptions) -> Option<f64> {
+        let mut vars = vars.clone();
+        let result = self.eval_rec(self.root.expect("Not evaluated"), &mut vars, options)?;
+        result.is_finite().then_some(result)
+    }
+
+    /// Evaluate the expression at `steps` evenly spaced values of `var` from `start` to `end`,
+    /// both included
+    ///
+    /// Points where the evaluation fails, like `1/x` at `0`, are `None`
+    pub fn sample(&self, var: &str, start: f64, end: f64, steps: usize) -> Vec<(f64, Option<f64>)> {
+        let step = if steps > 1 {
+            (end - start) / (steps - 1) as f64
+        } else {
+            0.0
+        };
+        let mut vars = HashMap::new();
+        (0..steps)
+            .map(|n| {
+                let x = start + step * n as f64;
+                vars.insert(var, x);
+                (x, self.eval_f64(&vars))
+            })
+            .collect()
+    }
+
+    /// The exact value of an expression without variables, like `2^3 - 10/5`
+    ///
+    /// Cheaper than running the simplifier. Returns `None` for anything with
+    /// variables, results that aren't integers and overflows
+    pub fn try_eval_const(&self) -> Option<Num> {
+        let value = self.eval_const_rec(self.root.expect("Not evaluated"))?;
+        value.is_integer().then(|| value.to_integer())
+    }
+
+    pub(crate) fn eval_const_rec(&self, i: Id) -> Option<Rational> {
+        fold_const(&self[i], |child| self.eval_const_rec(child))
+    }
+
+    /// The exact value of the subexpression at `i` rounded to a f64,
+    /// if [`NumberPreference::Rational`] is preferred and it is constant
+    pub(crate) fn eval_preferred(&self, i: Id, options: EvalOptions) -> Option<f64> {
+        if options.prefer != NumberPreference::Rational {
+            return None;
+        }
+        let value = self.eval_const_rec(i)?;
+        Some(*value.numer() as f64 / *value.denom() as f64)
+    }
+
+    fn eval_rec<'a>(
+        &'a self,
+        i: Id,
+        vars: &mut HashMap<&'a str, f64>,
+        options: EvalOptions,
+    ) -> Option<f64> {
+        use YafcLanguage::*;
+
+        let eval = |i: &Id, vars: &mut HashMap<&'a str, f64>| self.eval_rec(*i, vars, options);
+        let angle = |v: f64| match options.angle {
+            AngleMode::Radians => v,
+            AngleMode::Degrees => v.to_radians(),
+        };
+
+        if let Some(value) = self.eval_preferred(i, options) {
+            return Some(value);
+        }
+
+        Some(match &self.expr[i] {
+            Num(num) => *num as f64,
+            Float(float) => float.0,
+            Euler => std::f64::consts::E,
+            Var(var) | Const(var) => *vars.get(var.as_str())?,
+            Add([a, b]) => eval(a, vars)? + eval(b, vars)?,
+            Mul([a, b]) => eval(a, vars)? * eval(b, vars)?,
+            Pow([a, b]) => eval(a, vars)?.powf(eval(b, vars)?),
+            Max([a, b]) => eval(a, vars)?.max(eval(b, vars)?),
+            Min([a, b]) => eval(a, vars)?.min(eval(b, vars)?),
+            Log([a, b]) => eval(b, vars)?.ln() / eval(a, vars)?.ln(),
+            Eq([a, b]) => truth(eval(a, vars)? == eval(b, vars)?),
+            Lt([a, b]) => truth(eval(a, vars)? < eval(b, vars)?),
+            Le([a, b]) => truth(eval(a, vars)? <= eval(b, vars)?),
+            Gt([a, b]) => truth(eval(a, vars)? > eval(b, vars)?),
+            Ge([a, b]) => truth(eval(a, vars)? >= eval(b, vars)?),
+            And([a, b]) => truth(eval(a, vars)? != 0.0 && eval(b, vars)? != 0.0),
+            Or([a, b]) => truth(eval(a, vars)? != 0.0 || eval(b, vars)? != 0.0),
+            Fac(a) => factorial(eval(a, vars)?)?,
+            DoubleFac(a) => double_factorial(eval(a, vars)?)?,
+            Floor(a) => eval(a, vars)?.floor(),
+            Ceil(a) => eval(a, vars)?.ceil(),
+            Sin(a) => angle(eval(a, vars)?).sin(),
+            Cos(a) => angle(eval(a, vars)?).cos(),
+            Tan(a) => angle(eval(a, vars)?).tan(),
+            Abs(a) => eval(a, vars)?.abs(),
+            Opaque(a) => eval(a, vars)?,
+            Sum([index, from, to, body]) => {
+                let Var(index) = &self.expr[*index] else {
+                    return None;
+                };
+                let from = eval(from, vars)?.ceil() as i64;
+                let to = eval(to, vars)?.floor() as i64;
+                if to.saturating_sub(from) >= MAX_SUM_ITERATIONS {
+                    return None;
+                }
+
+                let outer = vars.get(index.as_str()).copied();
+                let mut sum = 0.0;
+                for k in from..=to {
+                    vars.insert(index.as_str(), k as f64);
+                    sum += eval(body, vars)?;
+                }
+                match outer {
+                    Some(outer) => vars.insert(index.as_str(), outer),
+                    None => vars.remove(index.as_str()),
+                };
+                sum
+            }
+            // expand user defined functions first
+            Call(..) => return None,
+            // the first branch that holds, `None` if none does
+            Piecewise(args) => {
+                let mut value = None;
+                for branch in args.chunks(2) {
+                    if eval(&branch[0], vars)? != 0.0 {
+                        value = Some(branch[1]);
+                        break;
+                    }
+                }
+                eval(&value?, vars)?
+            }
+            // no complex numbers
+            Imag | Matrix(_) | Row(_) => return None,
+        })
+    }
+}
+
+/// `n!` for a whole `n` from `0` to `170`, `171!` doesn't fit in a f64
+pub(crate) fn factorial(n: f64) -> Option<f64> {
+    if !(0.0..=170.0).contains(&n) || n.fract() != 0.0 {
+        return None;
+    }
+    Some((1..=n as u64).map(|k| k as f64).product())
+}
+
+/// `n!!` for a whole `n` from `0` to `300`, `301!!` doesn't fit in a f64
+pub(crate) fn double_factorial(n: f64) -> Option<f64> {
+    if !(0.0..=300.0).contains(&n) || n.fract() != 0.0 {
+        return None;
+    }
+    Some((1..=n as u64).rev().step_by(2).map(|k| k as f64).product())
+}
+
+/// `1` for true and `0` for false, like the relations evaluate to
+pub(crate) fn truth(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::{EvalOptions, NumberPreference};
+    use crate::ast::YafcExpr;
+    use std::collections::HashMap;
+
+    #[test]
+    fn rational_preference_is_exact() {
+        let expr = YafcExpr::parse_infix("1/49*49 - 1 + x").unwrap();
+        let float = EvalOptions::default();
+        let rational = EvalOptions {
+            prefer: NumberPreference::Rational,
+            ..float
+        };
+        let vars: HashMap<_, _> = [("x", 0.0)].into();
+
+        assert_ne!(expr.eval_f64_opt(&vars, float), Some(0.0));
+        assert_eq!(expr.eval_f64_opt(&vars, rational), Some(0.0));
+
+        let compiled = expr.compile_opt(&["x"], rational).unwrap();
+        assert_eq!(compiled(&[0.0]), 0.0);
+        assert_ne!(expr.compile_opt(&["x"], float).unwrap()(&[0.0]), 0.0);
+
+        let program = expr.to_bytecode_opt(rational).unwrap();
+        assert_eq!(program.run(&[0.0]), 0.0);
+        assert_ne!(expr.to_bytecode_opt(float).unwrap().run(&[0.0]), 0.0);
+    }
+
+    #[test]
+    fn long_sums_fail() {
+        let expr = YafcExpr::parse_infix("sum(k, 1, n, k)").unwrap();
+        let small: HashMap<_, _> = [("n", 100.0)].into();
+        let huge: HashMap<_, _> = [("n", 1e12)].into();
+        assert_eq!(expr.eval_f64(&small), Some(5050.0));
+        assert_eq!(expr.eval_f64(&huge), None);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{
+    ast::{BinOp, UnOp, YafcExpr, YafcLanguage},
+    simplifier::Simplifier,
+};
+use egg::Id;
+
+//
+
+impl YafcExpr {
+    /// Simplified antiderivative of the expression with respect to `var`,
+    /// without the constant of integration
+    ///
+    /// Only polynomial-like terms are supported: sums, constant multiples and
+    /// `x^n`, with `x^-1` integrating to `ln(|x|)`. Returns `None` for anything else
+    pub fn integrate(&self, var: &str) -> Option<YafcExpr> {
+        let mut integrator = Integrator {
+            src: self,
+            var,
+            out: YafcExpr::new(),
+        };
+        let root = integrator.integrate(self.root.expect("Not evaluated"))?;
+
+        let mut out = integrator.out;
+        out.root = Some(root);
+        Some(Simplifier::run(&out))
+    }
+}
+
+//
+
+struct Integrator<'a> {
+    src: &'a YafcExpr,
+    var: &'a str,
+    out: YafcExpr,
+}
+
+impl Integrator<'_> {
+    fn integrate(&mut self, i: Id) -> Option<Id> {
+        if !self.contains_var(i) {
+            // ∫c = c * x
+            let c = self.copy(i);
+            let var = self.out.make_var(self.var);
+            return Some(self.out.make_mul(c, var));
+        }
+
+        Some(match &self.src[i] {
+            YafcLanguage::Var(_) => {
+                let two = self.out.make_num(2);
+                self.power_rule(two)
+            }
+            YafcLanguage::Add([lhs, rhs]) => {
+                let lhs = self.integrate(*lhs)?;
+                let rhs = self.integrate(*rhs)?;
+                self.out.make_add(lhs, rhs)
+            }
+            YafcLanguage::Mul([lhs, rhs]) if !self.contains_var(*lhs) => {
+                let rhs = self.integrate(*rhs)?;
+                let lhs = self.copy(*lhs);
+                self.out.make_mul(lhs, rhs)
+            }
+            YafcLanguage::Mul([lhs, rhs]) if !self.contains_var(*rhs) => {
+                let lhs = self.integrate(*lhs)?;
+                let rhs = self.copy(*rhs);
+                self.out.make_mul(lhs, rhs)
+            }
+            YafcLanguage::Pow([base, exp])
+                if matches!(&self.src[*base], YafcLanguage::Var(v) if v.as_str() == self.var)
+                    && !self.contains_var(*exp) =>
+            {
+                let mut exp_inc = self.src.subexpr(*exp);
+                let exp_root = exp_inc.root.expect("Not evaluated");
+                let one = exp_inc.make_num(1);
+                exp_inc.root = Some(exp_inc.make_add(exp_root, one));
+                let exp_inc = Simplifier::run(&exp_inc);
+                // ∫x^-1 = ln(|x|)
+                if exp_inc[exp_inc.root.expect("Not evaluated")] == YafcLanguage::Num(0) {
+                    let var = self.copy(*base);
+                    let abs = self.out.make_un(UnOp::Abs, var);
+                    let e = self.out.add(YafcLanguage::Euler);
+                    return Some(self.out.make_bin(BinOp::Log, e, abs));
+                }
+
+                let exp_inc = self
+                    .out
+                    .copy_from(&exp_inc, exp_inc.root.expect("Not evaluated"));
+                self.power_rule(exp_inc)
+            }
+            YafcLanguage::Sum([index, from, to, body])
+                if !self.contains_var(*from)
+                    && !self.contains_var(*to)
+                    && !self.contains_var(*index) =>
+            {
+                let body = self.integrate(*body)?;
+                let index = self.copy(*index);
+                let from = self.copy(*from);
+                let to = self.copy(*to);
+                self.out.add(YafcLanguage::Sum([index, from, to, body]))
+            }
+            _ => return None,
+        })
+    }
+
+    /// `x^(n+1) / (n+1)` from `n+1`
+    fn power_rule(&mut self, exp_inc: Id) -> Id {
+        let var = self.out.make_var(self.var);
+        let pow = self.out.make_pow(var, exp_inc);
+        self.out.make_div(pow, exp_inc)
+    }
+
+    fn contains_var(&self, i: Id) -> bool {
+        self.src.contains_var_at(i, self.var)
+    }
+
+    fn copy(&mut self, i: Id) -> Id {
+        self.out.copy_from(self.src, i)
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::YafcExpr;
+
+    fn integrate(s: &str) -> Option<String> {
+        let expr = YafcExpr::parse_infix(s).unwrap();
+        expr.integrate("x").map(|expr| format!("{expr:#}"))
+    }
+
+    #[test]
+    fn reciprocals_integrate_to_logarithms() {
+        assert_eq!(integrate("x^-1").as_deref(), Some("ln(|x|)"));
+        assert_eq!(integrate("3/x").as_deref(), Some("3 * ln(|x|)"));
+        assert_eq!(integrate("x^2").as_deref(), Some("x ^ 3 / 3"));
+        assert_eq!(integrate("sin(x)"), None);
+    }
+}
@@ -1,5 +1,40 @@
+pub mod answer;
 pub mod ast;
+pub mod bytecode;
+pub mod compile;
+pub mod cse;
+pub mod define;
+pub mod diff;
+pub mod eval;
+pub mod integrate;
+pub mod matrix;
+pub mod ops;
+pub mod path;
+pub mod pattern;
+pub mod poly;
 pub mod simplifier;
+pub mod units;
+pub mod validate;
+
+use ast::YafcExpr;
+use lalrpop_util::{lexer::Token, ParseError};
+use simplifier::Simplifier;
+
+//
+
+/// Parse, simplify and print the result: `x + x` gives `2 * x`
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(yafc::simplify_str("x + x").unwrap(), "2 * x");
+/// assert_eq!(yafc::simplify_str("2^3 - 1").unwrap(), "7");
+/// assert!(yafc::simplify_str("x +").is_err());
+/// ```
+pub fn simplify_str(input: &str) -> Result<String, ParseError<usize, Token<'_>, String>> {
+    let expr = YafcExpr::parse_infix(input)?;
+    Ok(format!("{:#}", Simplifier::run(&expr)))
+}
 
 //
 
@@ -0,0 +1,291 @@
+use crate::ast::{Num, YafcExpr, YafcLanguage};
+use egg::{Id, Language};
+use std::fmt;
+
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixError {
+    /// Added or multiplied matrices of incompatible sizes, as rows and columns
+    Mismatch((usize, usize), (usize, usize)),
+
+    /// A matrix where only a scalar makes sense: `sin([[1]])` or `[[1]] + 1`
+    NotScalar,
+
+    /// A matrix power past [`MAX_MATRIX_POWER`], the elements would be far too large
+    PowerTooLarge,
+}
+
+/// The largest `n` of `A^n`: the elements aren't simplified in between, so even by
+/// squaring they grow with `n^2`
+pub const MAX_MATRIX_POWER: Num = 64;
+
+impl fmt::Display for MatrixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatrixError::Mismatch((a_rows, a_cols), (b_rows, b_cols)) => write!(
+                f,
+                "Incompatible matrix sizes `{a_rows}x{a_cols}` and `{b_rows}x{b_cols}`"
+            ),
+            MatrixError::NotScalar => write!(f, "Expected a scalar, got a matrix"),
+            MatrixError::PowerTooLarge => {
+                write!(f, "Matrix powers are limited to `^{MAX_MATRIX_POWER}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MatrixError {}
+
+/// A reduced subexpression, the ids are nodes of the output
+enum Value {
+    Scalar(Id),
+    Matrix(Vec<Vec<Id>>),
+}
+
+impl YafcExpr {
+    /// Check if the expression has a matrix anywhere in it
+    pub fn contains_matrix(&self) -> bool {
+        self.as_ref()
+            .iter()
+            .any(|node| matches!(node, YafcLanguage::Matrix(_)))
+    }
+
+    /// The elements of the matrix row by row, `None` if the expression isn't a matrix
+    pub fn as_matrix(&self) -> Option<Vec<Vec<YafcExpr>>> {
+        let YafcLanguage::Matrix(rows) = &self[self.root.expect("Not evaluated")] else {
+            return None;
+        };
+        let rows = rows
+            .iter()
+            .map(|&row| self[row].children().iter().map(|&item| self.subexpr(item)))
+            .map(Iterator::collect)
+            .collect();
+        Some(rows)
+    }
+
+    /// Carry out the matrix sums and products, so that only the elements are left
+    ///
+    /// Sums are element-wise, scalars multiply every element and `A^n` is `A * .. * A`
+    /// up to [`MAX_MATRIX_POWER`].
+    /// The elements themselves aren't simplified: `[[1, 2]] + [[3, 4]]` gives `[[1 + 3, 2 + 4]]`
+    pub fn reduce_matrices(&self) -> Result<YafcExpr, MatrixError> {
+        let mut out = YafcExpr::new();
+        let root = match self.reduce_rec(self.root.expect("Not evaluated"), &mut out)? {
+            Value::Scalar(root) => root,
+            Value::Matrix(rows) => out.make_matrix(rows).unwrap(),
+        };
+        out.root = Some(root);
+        Ok(out)
+    }
+
+    /// Apply `f` to every element of a matrix, or to the whole expression if it isn't one
+    pub(crate) fn map_elements(&self, mut f: impl FnMut(&YafcExpr) -> YafcExpr) -> YafcExpr {
+        let Some(rows) = self.as_matrix() else {
+            return f(self);
+        };
+        let mut out = YafcExpr::new();
+        let rows = rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|item| {
+                        let item = f(item);
+                        out.copy_from(&item, item.root.expect("Not evaluated"))
+                    })
+                    .collect()
+            })
+            .collect();
+        let root = out.make_matrix(rows).unwrap();
+        out.root = Some(root);
+        out
+    }
+
+    fn reduce_rec(&self, i: Id, out: &mut YafcExpr) -> Result<Value, MatrixError> {
+        use YafcLanguage::*;
+
+        match &self[i] {
+            Matrix(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|&row| {
+                        self[row]
+                            .children()
+                            .iter()
+                            .map(|&item| self.reduce_scalar(item, out))
+                            .collect()
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Value::Matrix(rows))
+            }
+            Add([lhs, rhs]) => match (self.reduce_rec(*lhs, out)?, self.reduce_rec(*rhs, out)?) {
+                (Value::Scalar(lhs), Value::Scalar(rhs)) => {
+                    Ok(Value::Scalar(out.make_add(lhs, rhs)))
+                }
+                (Value::Matrix(lhs), Value::Matrix(rhs)) => {
+                    if size(&lhs) != size(&rhs) {
+                        return Err(MatrixError::Mismatch(size(&lhs), size(&rhs)));
+                    }
+                    let rows = lhs
+                        .iter()
+                        .zip(&rhs)
+                        .map(|(lhs, rhs)| {
+                            lhs.iter()
+                                .zip(rhs)
+                                .map(|(&lhs, &rhs)| out.make_add(lhs, rhs))
+                                .collect()
+                        })
+                        .collect();
+                    Ok(Value::Matrix(rows))
+                }
+                _ => Err(MatrixError::NotScalar),
+            },
+            Mul([lhs, rhs]) => match (self.reduce_rec(*lhs, out)?, self.reduce_rec(*rhs, out)?) {
+                (Value::Scalar(lhs), Value::Scalar(rhs)) => {
+                    Ok(Value::Scalar(out.make_mul(lhs, rhs)))
+                }
+                (Value::Scalar(scalar), Value::Matrix(matrix)) => {
+                    Ok(Value::Matrix(scale(scalar, &matrix, out)))
+                }
+                (Value::Matrix(matrix), Value::Scalar(scalar)) => {
+                    Ok(Value::Matrix(scale(scalar, &matrix, out)))
+                }
+                (Value::Matrix(lhs), Value::Matrix(rhs)) => {
+                    Ok(Value::Matrix(product(&lhs, &rhs, out)?))
+                }
+            },
+            Pow([base, exp]) => match (self.reduce_rec(*base, out)?, &self[*exp]) {
+                (Value::Scalar(base), _) => {
+                    let exp = self.reduce_scalar(*exp, out)?;
+                    Ok(Value::Scalar(out.make_pow(base, exp)))
+                }
+                // only positive whole powers, there are no inverses
+                (Value::Matrix(_), Num(n)) if *n > MAX_MATRIX_POWER => {
+                    Err(MatrixError::PowerTooLarge)
+                }
+                // by squaring, `A^8` takes 3 products instead of 7
+                (Value::Matrix(base), Num(n)) if *n >= 1 => {
+                    let (mut n, mut square, mut pow) = (*n, base, None::<Vec<Vec<Id>>>);
+                    loop {
+                        if n % 2 == 1 {
+                            pow = Some(match pow {
+                                Some(pow) => product(&pow, &square, out)?,
+                                None => square.clone(),
+                            });
+                        }
+                        n /= 2;
+                        if n == 0 {
+                            break;
+                        }
+                        square = product(&square, &square, out)?;
+                    }
+                    Ok(Value::Matrix(pow.unwrap()))
+                }
+                (Value::Matrix(_), _) => Err(MatrixError::NotScalar),
+            },
+            node => {
+                let mut node = node.clone();
+                for child in node.children_mut() {
+                    *child = self.reduce_scalar(*child, out)?;
+                }
+                Ok(Value::Scalar(out.add(node)))
+            }
+        }
+    }
+
+    fn reduce_scalar(&self, i: Id, out: &mut YafcExpr) -> Result<Id, MatrixError> {
+        match self.reduce_rec(i, out)? {
+            Value::Scalar(i) => Ok(i),
+            Value::Matrix(_) => Err(MatrixError::NotScalar),
+        }
+    }
+}
+
+/// Rows and columns
+fn size(matrix: &[Vec<Id>]) -> (usize, usize) {
+    (matrix.len(), matrix[0].len())
+}
+
+fn scale(scalar: Id, matrix: &[Vec<Id>], out: &mut YafcExpr) -> Vec<Vec<Id>> {
+    matrix
+        .iter()
+        .map(|row| row.iter().map(|&item| out.make_mul(scalar, item)).collect())
+        .collect()
+}
+
+/// `lhs * rhs`, the columns of `lhs` have to match the rows of `rhs`
+fn product(
+    lhs: &[Vec<Id>],
+    rhs: &[Vec<Id>],
+    out: &mut YafcExpr,
+) -> Result<Vec<Vec<Id>>, MatrixError> {
+    let ((rows, inner), (rhs_rows, cols)) = (size(lhs), size(rhs));
+    if inner != rhs_rows {
+        return Err(MatrixError::Mismatch(size(lhs), size(rhs)));
+    }
+    let rows = (0..rows)
+        .map(|row| {
+            (0..cols)
+                .map(|col| {
+                    let mut sum = out.make_mul(lhs[row][0], rhs[0][col]);
+                    for k in 1..inner {
+                        let term = out.make_mul(lhs[row][k], rhs[k][col]);
+                        sum = out.make_add(sum, term);
+                    }
+                    sum
+                })
+                .collect()
+        })
+        .collect();
+    Ok(rows)
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::MatrixError;
+    use crate::{ast::YafcExpr, simplify_str};
+
+    fn reduced(s: &str) -> Result<YafcExpr, MatrixError> {
+        YafcExpr::parse_infix(s).unwrap().reduce_matrices()
+    }
+
+    #[test]
+    fn matrix_sums_and_products() {
+        assert_eq!(
+            simplify_str("[[1, 2], [3, 4]] + [[5, 6], [7, 8]]").unwrap(),
+            "[[6, 8], [10, 12]]"
+        );
+        assert_eq!(
+            simplify_str("[[1, 2], [3, 4]] * [[5, 6], [7, 8]]").unwrap(),
+            "[[19, 22], [43, 50]]"
+        );
+        assert_eq!(
+            simplify_str("2*[[1, 2], [3, 4]]").unwrap(),
+            "[[2, 4], [6, 8]]"
+        );
+        assert_eq!(
+            reduced("[[1, 2], [3, 4]] + [[1, 1]]").unwrap_err(),
+            MatrixError::Mismatch((2, 2), (1, 2))
+        );
+        assert_eq!(reduced("sin([[1]])").unwrap_err(), MatrixError::NotScalar);
+    }
+
+    #[test]
+    fn matrix_powers() {
+        assert_eq!(
+            simplify_str("[[1, 1], [0, 1]]^5").unwrap(),
+            "[[1, 5], [0, 1]]"
+        );
+        assert_eq!(
+            simplify_str("[[1, 1], [1, 0]]^10").unwrap(),
+            "[[89, 55], [55, 34]]"
+        );
+        assert_eq!(simplify_str("[[1, 2]]^1").unwrap(), "[[1, 2]]");
+        assert_eq!(
+            reduced("[[1, 1], [1, 0]]^1000000000").unwrap_err(),
+            MatrixError::PowerTooLarge
+        );
+    }
+}
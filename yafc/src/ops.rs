@@ -0,0 +1,101 @@
+use crate::{
+    ast::{Num, YafcExpr, YafcLanguage},
+    simplifier::Simplifier,
+};
+use egg::Id;
+use std::ops;
+
+//
+
+impl YafcExpr {
+    /// The variable `name` on its own
+    pub fn var(name: &str) -> Self {
+        let mut out = YafcExpr::new();
+        out.root = Some(out.make_var(name));
+        out
+    }
+
+    /// The arbitrary constant `name`, printed like a variable but never substituted
+    pub fn constant(name: &str) -> Self {
+        let mut out = YafcExpr::new();
+        out.root = Some(out.add(YafcLanguage::Const(name.into())));
+        out
+    }
+
+    /// `self ^ exp`
+    pub fn pow(self, exp: impl Into<YafcExpr>) -> Self {
+        self.join(exp.into(), YafcExpr::make_pow)
+    }
+
+    /// `-self`, simplified
+    pub fn negate(&self) -> Self {
+        Simplifier::run(&-self.clone())
+    }
+
+    /// `1 / self`, simplified
+    pub fn reciprocal(&self) -> Self {
+        Simplifier::run(&self.clone().pow(-1))
+    }
+
+    /// Mathematical equality: `x + y` equals `y + x` here but not with `==`,
+    /// which compares the trees node by node
+    pub fn math_eq(&self, other: &YafcExpr) -> bool {
+        Simplifier::equivalent(self, other)
+    }
+
+    fn join(self, rhs: YafcExpr, f: impl FnOnce(&mut YafcExpr, Id, Id) -> Id) -> Self {
+        let mut out = YafcExpr::new();
+        let lhs = out.copy_from(&self, self.root.expect("Not evaluated"));
+        let rhs = out.copy_from(&rhs, rhs.root.expect("Not evaluated"));
+        out.root = Some(f(&mut out, lhs, rhs));
+        out
+    }
+}
+
+impl From<Num> for YafcExpr {
+    fn from(num: Num) -> Self {
+        let mut out = YafcExpr::new();
+        out.root = Some(out.make_num(num));
+        out
+    }
+}
+
+impl<T: Into<YafcExpr>> ops::Add<T> for YafcExpr {
+    type Output = Self;
+
+    fn add(self, rhs: T) -> Self {
+        self.join(rhs.into(), YafcExpr::make_add)
+    }
+}
+
+impl<T: Into<YafcExpr>> ops::Sub<T> for YafcExpr {
+    type Output = Self;
+
+    fn sub(self, rhs: T) -> Self {
+        self.join(rhs.into(), YafcExpr::make_sub)
+    }
+}
+
+impl<T: Into<YafcExpr>> ops::Mul<T> for YafcExpr {
+    type Output = Self;
+
+    fn mul(self, rhs: T) -> Self {
+        self.join(rhs.into(), YafcExpr::make_mul)
+    }
+}
+
+impl<T: Into<YafcExpr>> ops::Div<T> for YafcExpr {
+    type Output = Self;
+
+    fn div(self, rhs: T) -> Self {
+        self.join(rhs.into(), YafcExpr::make_div)
+    }
+}
+
+impl ops::Neg for YafcExpr {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        YafcExpr::from(-1) * self
+    }
+}
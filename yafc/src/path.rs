@@ -0,0 +1,75 @@
+use crate::ast::YafcExpr;
+use egg::{Id, Language};
+
+//
+
+/// Child indices from the root to a subexpression, the root itself is `[]`
+pub type Path = Vec<usize>;
+
+impl YafcExpr {
+    /// Every subexpression with its path, in pre order: `x + 1` gives
+    /// `[]` for the sum, `[0]` for `x` and `[1]` for `1`
+    ///
+    /// The ids are nodes of `self`, see [`YafcExpr::subexpr`]
+    pub fn with_paths(&self) -> Vec<(Path, Id)> {
+        let mut out = vec![];
+        self.with_paths_rec(self.root.expect("Not evaluated"), &mut vec![], &mut out);
+        out
+    }
+
+    /// Copies of every subexpression in pre order, the expression itself first
+    pub fn subexpressions(&self) -> impl Iterator<Item = YafcExpr> + '_ {
+        self.with_paths().into_iter().map(|(_, i)| self.subexpr(i))
+    }
+
+    fn with_paths_rec(&self, i: Id, path: &mut Path, out: &mut Vec<(Path, Id)>) {
+        out.push((path.clone(), i));
+        for (n, &child) in self[i].children().iter().enumerate() {
+            path.push(n);
+            self.with_paths_rec(child, path, out);
+            path.pop();
+        }
+    }
+
+    /// The node at `path`, `None` if the path leads nowhere
+    pub fn at_path(&self, path: &[usize]) -> Option<Id> {
+        path.iter()
+            .try_fold(self.root.expect("Not evaluated"), |i, &n| {
+                self[i].children().get(n).copied()
+            })
+    }
+
+    /// A copy with the subexpression at `path` replaced by `value`,
+    /// `None` if the path leads nowhere
+    pub fn replace_at(&self, path: &[usize], value: &YafcExpr) -> Option<YafcExpr> {
+        let mut out = YafcExpr::new();
+        let root = out.replace_at_rec(self, self.root.expect("Not evaluated"), path, value)?;
+        out.root = Some(root);
+        Some(out)
+    }
+
+    fn replace_at_rec(
+        &mut self,
+        src: &YafcExpr,
+        i: Id,
+        path: &[usize],
+        value: &YafcExpr,
+    ) -> Option<Id> {
+        let Some((&first, rest)) = path.split_first() else {
+            return Some(self.copy_from(value, value.root.expect("Not evaluated")));
+        };
+        if first >= src[i].len() {
+            return None;
+        }
+
+        let mut node = src[i].clone();
+        for (n, child) in node.children_mut().iter_mut().enumerate() {
+            *child = if n == first {
+                self.replace_at_rec(src, *child, rest, value)?
+            } else {
+                self.copy_from(src, *child)
+            };
+        }
+        Some(self.add(node))
+    }
+}
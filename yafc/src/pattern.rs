@@ -0,0 +1,46 @@
+use crate::ast::{YafcExpr, YafcLanguage};
+use egg::{Id, Language};
+use std::collections::HashMap;
+
+//
+
+/// Subexpressions captured by [`YafcExpr::match_pattern`], by wildcard name without the `?`
+pub type Captures = HashMap<String, YafcExpr>;
+
+impl YafcExpr {
+    /// Match the whole expression against `pattern`, where variables like `?a`
+    /// are wildcards that capture any subexpression
+    ///
+    /// `?a + ?b` matches `x + 2` with `a = x` and `b = 2`. A wildcard used twice
+    /// has to capture equal subexpressions. Operands are matched in order,
+    /// `?a + 2` doesn't match `2 + x`
+    pub fn match_pattern(&self, pattern: &YafcExpr) -> Option<Captures> {
+        let mut captures = Captures::new();
+        let root = self.root.expect("Not evaluated");
+        let pattern_root = pattern.root.expect("Not evaluated");
+        self.match_rec(root, pattern, pattern_root, &mut captures)
+            .then_some(captures)
+    }
+
+    fn match_rec(&self, i: Id, pattern: &YafcExpr, p: Id, captures: &mut Captures) -> bool {
+        match &pattern[p] {
+            YafcLanguage::Var(var) if var.as_str().starts_with('?') => {
+                let captured = self.subexpr(i);
+                let name = &var.as_str()[1..];
+                match captures.get(name) {
+                    Some(other) => *other == captured,
+                    None => {
+                        captures.insert(name.to_string(), captured);
+                        true
+                    }
+                }
+            }
+            node if node.matches(&self[i]) => node
+                .children()
+                .iter()
+                .zip(self[i].children())
+                .all(|(&p, &i)| self.match_rec(i, pattern, p, captures)),
+            _ => false,
+        }
+    }
+}
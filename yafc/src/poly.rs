@@ -0,0 +1,309 @@
+use crate::{
+    ast::{BinOp, YafcExpr, YafcLanguage},
+    simplifier::Simplifier,
+};
+use egg::{Id, Language};
+use std::collections::BTreeMap;
+
+//
+
+/// Coefficients of a polynomial by degree
+pub type Coefficients = BTreeMap<i64, YafcExpr>;
+
+/// Powers like `(x + 1)^100000` aren't multiplied out past this degree
+pub const MAX_DEGREE: i64 = 1000;
+
+impl YafcExpr {
+    /// Collect the expression into a polynomial in `var`
+    ///
+    /// Returns `None` if the expression isn't a polynomial in `var`, like `x^y` or `x^-1`,
+    /// or if a power of it has a degree over [`MAX_DEGREE`]. Zero coefficients are left out.
+    pub fn coefficients(&self, var: &str) -> Option<Coefficients> {
+        let mut collector = Collector {
+            src: self,
+            var,
+            out: YafcExpr::new(),
+        };
+        let terms = collector.collect(self.root.expect("Not evaluated"))?;
+
+        Some(
+            terms
+                .into_iter()
+                .filter(|(_, coeff)| collector.out[*coeff] != YafcLanguage::Num(0))
+                .map(|(degree, coeff)| (degree, collector.out.subexpr(coeff)))
+                .collect(),
+        )
+    }
+
+    /// The degree and the coefficient of the highest degree term in `var`
+    ///
+    /// `3*x^2 + x` gives `(2, 3)` and constants give `(0, c)`
+    pub fn leading_term(&self, var: &str) -> Option<(i64, YafcExpr)> {
+        let mut coeffs = self.coefficients(var)?;
+        Some(coeffs.pop_last().unwrap_or_else(|| {
+            let mut zero = YafcExpr::new();
+            zero.root = Some(zero.make_num(0));
+            (0, zero)
+        }))
+    }
+
+    /// The highest sum of the exponents of the variables in a term: `x^2*y + x` gives `3`
+    ///
+    /// Returns `None` if the expression isn't a polynomial in its variables
+    pub fn total_degree(&self) -> Option<i64> {
+        // every variable `v` is scaled to `#t * v`, the degree in `#t` is the total degree
+        let mut scaled = YafcExpr::new();
+        let mut ids = vec![];
+        for node in self.as_ref() {
+            let node = node.clone().map_children(|child| ids[usize::from(child)]);
+            let id = match node {
+                YafcLanguage::Var(_) => {
+                    let var = scaled.add(node);
+                    let t = scaled.make_var("#t");
+                    scaled.make_mul(t, var)
+                }
+                node => scaled.add(node),
+            };
+            ids.push(id);
+        }
+        scaled.root = Some(ids[usize::from(self.root.expect("Not evaluated"))]);
+
+        let mut coeffs = scaled.coefficients("#t")?;
+        while let Some((degree, coeff)) = coeffs.pop_last() {
+            if !Simplifier::run(&coeff).is_zero() {
+                return Some(degree);
+            }
+        }
+        Some(0)
+    }
+
+    /// The slope and the intercept of a line in `var`: `3*x + 2` gives `(3, 2)`
+    ///
+    /// Returns `None` if the expression isn't a polynomial in `var` of degree 1 or less
+    pub fn as_linear(&self, var: &str) -> Option<(YafcExpr, YafcExpr)> {
+        let mut coeffs = self.coefficients(var)?;
+        if coeffs.keys().any(|&degree| degree > 1) {
+            return None;
+        }
+        let slope = coeffs.remove(&1).unwrap_or_else(|| YafcExpr::from(0));
+        let intercept = coeffs.remove(&0).unwrap_or_else(|| YafcExpr::from(0));
+        Some((slope, intercept))
+    }
+
+    /// The simplified remainder of the polynomial in `var` after using `relation` to
+    /// rewrite the powers of `var` down: `x^3` with `x^2 = x + 1` gives `2 * x + 1`
+    ///
+    /// Returns `None` if either side isn't a polynomial in `var`,
+    /// or if `relation` isn't an equation with `var` in it
+    pub fn reduce_modulo(&self, var: &str, relation: &YafcExpr) -> Option<YafcExpr> {
+        let (BinOp::Eq, lhs, rhs) = relation.as_relation()? else {
+            return None;
+        };
+        let mut modulus = (lhs - rhs).coefficients(var)?;
+        let (degree, leading) = modulus.pop_last()?;
+        if degree < 1 {
+            return None;
+        }
+
+        // `leading * x^degree` is replaced by the rest of the modulus, negated
+        let mut coeffs = self.coefficients(var)?;
+        while let Some((n, coeff)) = coeffs.pop_last() {
+            if n < degree {
+                coeffs.insert(n, coeff);
+                break;
+            }
+            let coeff = Simplifier::run(&coeff);
+            if coeff.is_zero() {
+                continue;
+            }
+            for (m, term) in &modulus {
+                let term = -(coeff.clone() * term.clone()) / leading.clone();
+                let sum = match coeffs.remove(&(n - degree + m)) {
+                    Some(other) => other + term,
+                    None => term,
+                };
+                coeffs.insert(n - degree + m, sum);
+            }
+        }
+
+        let x = YafcExpr::var(var);
+        let terms = coeffs
+            .into_iter()
+            .map(|(n, coeff)| coeff * x.clone().pow(n));
+        let sum = YafcExpr::from_operands(BinOp::Add, terms).unwrap_or_else(|| YafcExpr::from(0));
+        Some(Simplifier::run(&sum))
+    }
+
+    /// Rewrite the polynomial in `var` into Horner form:
+    /// `a*x^2 + b*x + c` becomes `(a*x + b)*x + c`
+    ///
+    /// Returns `None` if the expression isn't a polynomial in `var`
+    pub fn to_horner(&self, var: &str) -> Option<YafcExpr> {
+        let mut coeffs = self.coefficients(var)?;
+        let Some((degree, leading)) = coeffs.pop_last() else {
+            return Some(YafcExpr::from(0));
+        };
+
+        let x = YafcExpr::var(var);
+        Some((0..degree).rev().fold(leading, |acc, degree| {
+            let acc = match acc[acc.root.expect("Not evaluated")] {
+                YafcLanguage::Num(1) => x.clone(),
+                _ => acc * x.clone(),
+            };
+            match coeffs.remove(&degree) {
+                Some(coeff) => acc + coeff,
+                None => acc,
+            }
+        }))
+    }
+}
+
+//
+
+type Terms = BTreeMap<i64, Id>;
+
+struct Collector<'a> {
+    src: &'a YafcExpr,
+    var: &'a str,
+    out: YafcExpr,
+}
+
+impl Collector<'_> {
+    fn collect(&mut self, i: Id) -> Option<Terms> {
+        match &self.src[i] {
+            YafcLanguage::Var(var) if var.as_str() == self.var => {
+                let one = self.out.make_num(1);
+                Some([(1, one)].into())
+            }
+            YafcLanguage::Add([lhs, rhs]) => {
+                let lhs = self.collect(*lhs)?;
+                let rhs = self.collect(*rhs)?;
+                Some(self.add(lhs, rhs))
+            }
+            YafcLanguage::Mul([lhs, rhs]) => {
+                let lhs = self.collect(*lhs)?;
+                let rhs = self.collect(*rhs)?;
+                Some(self.mul(&lhs, &rhs))
+            }
+            YafcLanguage::Pow([base, exp]) if self.contains_var(*base) => {
+                let YafcLanguage::Num(exp) = self.src[*exp] else {
+                    return None;
+                };
+                if exp < 0 {
+                    return None;
+                }
+
+                let base = self.collect(*base)?;
+                let degree = base.keys().next_back().copied().unwrap_or(0);
+                if degree.checked_mul(exp)? > MAX_DEGREE {
+                    return None;
+                }
+                let one = self.out.make_num(1);
+                let mut result = [(0, one)].into();
+                for _ in 0..exp {
+                    result = self.mul(&result, &base);
+                }
+                Some(result)
+            }
+            _ if self.contains_var(i) => None,
+            _ => {
+                let constant = self.out.copy_from(self.src, i);
+                Some([(0, constant)].into())
+            }
+        }
+    }
+
+    fn add(&mut self, mut lhs: Terms, rhs: Terms) -> Terms {
+        for (degree, coeff) in rhs {
+            let coeff = match lhs.get(&degree) {
+                Some(&other) => self.add_coeff(other, coeff),
+                None => coeff,
+            };
+            lhs.insert(degree, coeff);
+        }
+        lhs
+    }
+
+    fn mul(&mut self, lhs: &Terms, rhs: &Terms) -> Terms {
+        let mut result = Terms::new();
+        for (&l_degree, &l_coeff) in lhs {
+            for (&r_degree, &r_coeff) in rhs {
+                let coeff = self.mul_coeff(l_coeff, r_coeff);
+                result = self.add(result, [(l_degree + r_degree, coeff)].into());
+            }
+        }
+        result
+    }
+
+    fn add_coeff(&mut self, lhs: Id, rhs: Id) -> Id {
+        match (&self.out[lhs], &self.out[rhs]) {
+            (YafcLanguage::Num(0), _) => rhs,
+            (_, YafcLanguage::Num(0)) => lhs,
+            (YafcLanguage::Num(a), YafcLanguage::Num(b)) => match a.checked_add(*b) {
+                Some(num) => self.out.make_num(num),
+                None => self.out.make_add(lhs, rhs),
+            },
+            _ => self.out.make_add(lhs, rhs),
+        }
+    }
+
+    fn mul_coeff(&mut self, lhs: Id, rhs: Id) -> Id {
+        match (&self.out[lhs], &self.out[rhs]) {
+            (YafcLanguage::Num(0), _) | (_, YafcLanguage::Num(0)) => self.out.make_num(0),
+            (YafcLanguage::Num(1), _) => rhs,
+            (_, YafcLanguage::Num(1)) => lhs,
+            (YafcLanguage::Num(a), YafcLanguage::Num(b)) => match a.checked_mul(*b) {
+                Some(num) => self.out.make_num(num),
+                None => self.out.make_mul(lhs, rhs),
+            },
+            _ => self.out.make_mul(lhs, rhs),
+        }
+    }
+
+    fn contains_var(&self, i: Id) -> bool {
+        self.src.contains_var_at(i, self.var)
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use crate::{ast::YafcExpr, simplifier::Simplifier};
+
+    #[test]
+    fn powers_reduce_modulo_a_relation() {
+        let parse = |s| YafcExpr::parse_infix(s).unwrap();
+        let reduced = |s, relation| {
+            let remainder = parse(s).reduce_modulo("x", &parse(relation));
+            remainder.map(|remainder| format!("{remainder:#}"))
+        };
+        assert_eq!(reduced("x^3", "x^2 = x + 1").unwrap(), "2 * x + 1");
+        assert_eq!(reduced("x^4 + x", "x^2 = x + 1").unwrap(), "4 * x + 2");
+        assert_eq!(reduced("x^2", "x + 1"), None);
+        assert_eq!(reduced("x^2", "y = 1"), None);
+    }
+
+    #[test]
+    fn other_symbols_are_coefficients() {
+        let coeffs = YafcExpr::parse_infix("a*x + b*x + c")
+            .unwrap()
+            .coefficients("x")
+            .unwrap();
+        let coeffs: Vec<(i64, String)> = coeffs
+            .into_iter()
+            .map(|(degree, coeff)| (degree, format!("{:#}", Simplifier::run(&coeff))))
+            .collect();
+        assert_eq!(coeffs, [(0, "c".to_string()), (1, "a + b".to_string())]);
+    }
+
+    #[test]
+    fn huge_powers_are_not_expanded() {
+        let coefficients = |s| YafcExpr::parse_infix(s).unwrap().coefficients("x");
+        let coeffs = coefficients("(x + 1)^3").unwrap();
+        let coeffs: Vec<String> = coeffs.values().map(|c| format!("{c}")).collect();
+        assert_eq!(coeffs, ["1", "3", "3", "1"]);
+        assert_eq!(coefficients("(x + 1)^100000"), None);
+        assert_eq!(coefficients("(2*x)^2").unwrap().len(), 1);
+    }
+}
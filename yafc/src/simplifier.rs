@@ -1,70 +1,1750 @@
-use crate::ast::{Num, YafcExpr, YafcLanguage};
-use egg::{merge_max, rewrite, Analysis, CostFunction, Extractor, Id, Language, Rewrite, Runner};
+use crate::{
+    ast::{json_string, BinOp, Num, Rational, YafcExpr, YafcLanguage},
+    eval::NumberPreference,
+};
+use egg::{
+    merge_max, rewrite, Analysis, Applier, AstSize, CostFunction, EClass, EGraph, ENodeOrVar,
+    Extractor, FlatTerm, Id, Language, PatternAst, RecExpr, Rewrite, Runner, Subst, Symbol, Var,
+};
+use num_traits::{CheckedAdd, CheckedMul, One, Signed, ToPrimitive, Zero};
 use once_cell::sync::Lazy;
-use std::time::Duration;
+use std::{cmp::Ordering, collections::HashMap, fmt, time::Duration};
 
 //
 
 pub struct Simplifier;
 
+#[cfg(test)]
+thread_local! {
+    /// How many expressions the rewrites changed, the rest took the already simplified path
+    static REBUILDS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SimplifyOptions {
+    /// Keep the terms of the top level sum in the order they were written,
+    /// combined terms take the place of their first occurrence
+    pub stable_order: bool,
+
+    /// Skip the rewrites if a sum has more terms than this, to cap the work on huge inputs.
+    /// The terms are still sorted and their numbers added up
+    pub max_terms: Option<usize>,
+
+    /// Variables that are sorted first, in this order: `y` first gives `y + x`.
+    /// The rest are sorted alphabetically after them
+    pub var_order: Vec<String>,
+
+    /// Leave the numbers as they were written, `2 + 3` stays `2 + 3`
+    /// but `x + x` is still `2 * x`. Subtractions are still cancelled out
+    pub structural: bool,
+
+    /// Cancel factorials that differ by a whole number: `n! / (n - 1)!` is `n`
+    pub combinatorics: bool,
+
+    /// Decimals like `0.5` are simplified as the exact fractions they are written as.
+    /// If the input has any, [`NumberPreference::Float`] writes the fractions of the result
+    /// as decimals again: `0.25 + 1/4` is `0.5`, and [`NumberPreference::Rational`] keeps
+    /// them exact: `1/2`. Inputs without decimals are simplified the same either way
+    pub prefer: NumberPreference,
+}
+
+impl SimplifyOptions {
+    /// Only the rewrites of the structure, see [`SimplifyOptions::structural`]
+    pub fn structural_only() -> Self {
+        Self {
+            structural: true,
+            ..<_>::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimplifyError {
+    /// The input or the result has more nodes than the budget
+    TooLarge,
+}
+
+impl fmt::Display for SimplifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimplifyError::TooLarge => write!(f, "Expression is too large to simplify"),
+        }
+    }
+}
+
+impl std::error::Error for SimplifyError {}
+
+/// Something [`Simplifier::run_checked`] took for granted to get its result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A divisor was cancelled out: `x / x` is `1` only if `x` isn't `0`
+    NonzeroAssumed(YafcExpr),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::NonzeroAssumed(expr) => write!(f, "Assumed that `{expr:#}` is nonzero"),
+        }
+    }
+}
+
+/// A rewrite in [`Simplifier::run_explained`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// The name of the rewrite, like `add-eq` for `a + a = 2 * a`.
+    /// Rules used right to left end with `-rev`
+    pub rule: String,
+
+    /// The subexpression before the rewrite
+    pub before: YafcExpr,
+
+    /// The subexpression after the rewrite
+    pub after: YafcExpr,
+
+    /// The subexpressions matched by the variables of the rule, in the order they appear
+    /// in its pattern: both `x` of `x + x` for `add-eq`
+    pub operands: Vec<YafcExpr>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.rule)?;
+        for (n, operand) in self.operands.iter().enumerate() {
+            let sep = match n {
+                0 => " on",
+                n if n + 1 == self.operands.len() => " and",
+                _ => ",",
+            };
+            write!(f, "{sep} `{operand:#}`")?;
+        }
+        write!(f, ": `{:#}` became `{:#}`", self.before, self.after)
+    }
+}
+
+impl Explanation {
+    /// JSON for frontends: `{"rule": "add-eq", "before": .., "after": .., "operands": [..]}`,
+    /// with the expressions as in [`YafcExpr::to_json`]
+    pub fn to_json(&self) -> String {
+        let operands: Vec<String> = self.operands.iter().map(YafcExpr::to_json).collect();
+        format!(
+            "{{\"rule\": {}, \"before\": {}, \"after\": {}, \"operands\": [{}]}}",
+            json_string(&self.rule),
+            self.before.to_json(),
+            self.after.to_json(),
+            operands.join(", ")
+        )
+    }
+
+    /// The steps of [`Simplifier::run_explained`] as a JSON array
+    pub fn trace_to_json(steps: &[Explanation]) -> String {
+        let steps: Vec<String> = steps.iter().map(Explanation::to_json).collect();
+        format!("[{}]", steps.join(", "))
+    }
+}
+
 impl Simplifier {
     pub fn run(in_expr: &YafcExpr) -> YafcExpr {
-        let runner = Runner::<YafcLanguage, ConstFold>::default()
-            .with_time_limit(Duration::from_millis(100))
-            .with_expr(in_expr)
-            .run(&*RULES);
+        Self::run_opt(in_expr, <_>::default())
+    }
+
+    /// [`Simplifier::run`] that also reports the assumptions behind the result
+    ///
+    /// Every divisor of the input that is gone from the result was assumed to be nonzero
+    pub fn run_checked(in_expr: &YafcExpr) -> (YafcExpr, Vec<Warning>) {
+        let simplified = Self::run(in_expr);
+        let kept = divisors(&simplified);
+
+        let mut warnings = vec![];
+        for divisor in divisors(in_expr) {
+            let divisor = Self::run(&divisor);
+            let warning = Warning::NonzeroAssumed(divisor.clone());
+            if !kept.contains(&divisor) && !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+        (simplified, warnings)
+    }
+
+    /// [`Simplifier::run`] that refuses inputs with more than `budget` nodes
+    ///
+    /// Expanded sums can make the result larger than the input, so it is checked too
+    pub fn run_with_budget(in_expr: &YafcExpr, budget: usize) -> Result<YafcExpr, SimplifyError> {
+        if in_expr.node_count() > budget {
+            return Err(SimplifyError::TooLarge);
+        }
+        let simplified = Self::run(in_expr);
+        if simplified.node_count() > budget {
+            return Err(SimplifyError::TooLarge);
+        }
+        Ok(simplified)
+    }
+
+    /// [`Simplifier::run`] on every expression, split over all cores with the `parallel` feature
+    ///
+    /// Each core gets one contiguous chunk on a scoped thread instead of a work-stealing
+    /// pool like rayon, so the feature doesn't pull in any dependencies. The results
+    /// are in the same order as `exprs`.
+    pub fn run_batch(exprs: &[YafcExpr]) -> Vec<YafcExpr> {
+        #[cfg(feature = "parallel")]
+        {
+            let threads = std::thread::available_parallelism().map_or(1, usize::from);
+            let chunk_len = exprs.len().div_ceil(threads).max(1);
+            std::thread::scope(|scope| {
+                let chunks: Vec<_> = exprs
+                    .chunks(chunk_len)
+                    .map(|chunk| scope.spawn(|| chunk.iter().map(Self::run).collect::<Vec<_>>()))
+                    .collect();
+                chunks
+                    .into_iter()
+                    .flat_map(|chunk| chunk.join().unwrap())
+                    .collect()
+            })
+        }
+        #[cfg(not(feature = "parallel"))]
+        exprs.iter().map(Self::run).collect()
+    }
+
+    pub fn run_opt(in_expr: &YafcExpr, options: SimplifyOptions) -> YafcExpr {
+        if !in_expr
+            .as_ref()
+            .iter()
+            .any(|node| matches!(node, YafcLanguage::Float(_)))
+        {
+            return Self::run_exact(in_expr, options);
+        }
+        let prefer = options.prefer;
+        let simplified = Self::run_exact(&floats_to_rationals(in_expr), options);
+        match prefer {
+            NumberPreference::Float => rationals_to_floats(&simplified),
+            NumberPreference::Rational => simplified,
+        }
+    }
+
+    fn run_exact(in_expr: &YafcExpr, options: SimplifyOptions) -> YafcExpr {
+        // the written numbers are hidden like opaque subexpressions, except for the
+        // `-1` of `a - b` so that `x - x` is still `0`
+        if options.structural {
+            let options = SimplifyOptions {
+                structural: false,
+                ..options
+            };
+            let is_literal =
+                |node: &YafcLanguage| matches!(node, YafcLanguage::Num(num) if *num != -1);
+            return Self::with_hidden(in_expr, "#n", is_literal, |expr| {
+                let simplified = Self::run_exact(expr, options);
+                let mut ordered = YafcExpr::new();
+                let root = simplified.root.expect("Not evaluated");
+                let root = hidden_factors_first(&simplified, root, "#n", &mut ordered);
+                ordered.root = Some(root);
+                ordered
+            });
+        }
+        // the rewrites would take `A * B` to be `B * A`, so the matrices are
+        // multiplied out first and only their elements are simplified
+        if in_expr.contains_matrix() {
+            return match in_expr.reduce_matrices() {
+                Ok(reduced) => reduced.map_elements(|item| Self::run_exact(item, options.clone())),
+                Err(_) => in_expr.clone(),
+            };
+        }
+        // branches with constant conditions are picked before and after the rewrites,
+        // the condition of `piecewise(x - x = 0, ..)` is only constant after them
+        if let Some(selected) = select_branches(in_expr) {
+            return Self::run_exact(&selected, options);
+        }
+
+        let simplified = Self::with_opaque_hidden(in_expr, |expr| {
+            let root = expr.root.expect("Not evaluated");
+            let simplified = match options.max_terms {
+                Some(max_terms) if max_sum_terms(expr, root) > max_terms => {
+                    tracing::debug!("too many terms, skipping the rewrites");
+                    canonical_order(expr, root)
+                }
+                _ if options.combinatorics => {
+                    Self::run_visible(&combine_like_terms(expr), false, &COMBINATORICS_RULES)
+                }
+                _ => Self::run_visible(&combine_like_terms(expr), false, &RULES),
+            };
+            let simplified = match options.var_order.as_slice() {
+                [] => simplified,
+                var_order => canonical_order_by(
+                    &simplified,
+                    simplified.root.expect("Not evaluated"),
+                    var_order,
+                ),
+            };
+            if options.stable_order {
+                stable_order(expr, &simplified)
+            } else {
+                simplified
+            }
+        });
+        match select_branches(&simplified) {
+            Some(selected) => Self::run_exact(&selected, options),
+            None => simplified,
+        }
+    }
+
+    /// Simplify and move roots out of denominators: `1/sqrt(x)` becomes `sqrt(x)/x`
+    pub fn rationalize(in_expr: &YafcExpr) -> YafcExpr {
+        Self::with_opaque_hidden(in_expr, |expr| Self::run_visible(expr, true, &RULES))
+    }
+
+    /// Simplify only the subexpression at `path` and leave the rest as it is,
+    /// `None` if the path leads nowhere
+    pub fn simplify_at(in_expr: &YafcExpr, path: &[usize]) -> Option<YafcExpr> {
+        let i = in_expr.at_path(path)?;
+        let simplified = Self::run(&in_expr.subexpr(i));
+        in_expr.replace_at(path, &simplified)
+    }
+
+    /// Only keep rewrites that make the expression strictly smaller
+    ///
+    /// Repeats until the node count doesn't go down, so `x + x + 0` loses the `+ 0`
+    /// but `(a + b) * c` is kept like it was written
+    pub fn minimize(in_expr: &YafcExpr) -> YafcExpr {
+        Self::with_opaque_hidden(in_expr, Self::minimize_visible)
+    }
+
+    fn with_opaque_hidden(in_expr: &YafcExpr, f: impl FnOnce(&YafcExpr) -> YafcExpr) -> YafcExpr {
+        let is_opaque = |node: &YafcLanguage| matches!(node, YafcLanguage::Opaque(_));
+        Self::with_hidden(in_expr, "#", is_opaque, f)
+    }
+
+    /// Run `f` with the subexpressions matching `is_hidden` behind placeholder variables
+    /// named `prefix` and a number, and put them back in the result
+    fn with_hidden(
+        in_expr: &YafcExpr,
+        prefix: &str,
+        is_hidden: impl Fn(&YafcLanguage) -> bool,
+        f: impl FnOnce(&YafcExpr) -> YafcExpr,
+    ) -> YafcExpr {
+        let mut hidden = vec![];
+        let mut stripped = YafcExpr::new();
+        let mut hider = Hider {
+            prefix,
+            is_hidden,
+            hidden: &mut hidden,
+        };
+        let root = hider.hide(in_expr, in_expr.root.expect("Not evaluated"), &mut stripped);
+        stripped.root = Some(root);
+
+        hidden.iter().fold(f(&stripped), |expr, (name, opaque)| {
+            expr.substitute(name, opaque)
+        })
+    }
+
+    fn run_visible(
+        in_expr: &YafcExpr,
+        rationalize: bool,
+        rules: &[Rewrite<YafcLanguage, ConstFold>],
+    ) -> YafcExpr {
+        let runner = Self::runner().with_expr(in_expr).run(rules);
 
         let extractor = Extractor::new(&runner.egraph, CostFn);
 
         let (cost, expr) = extractor.find_best(runner.roots[0]);
-        tracing::debug!("cost={cost},expr={}", expr.pretty(50));
+        tracing::debug!("cost={cost:?},expr={}", expr.pretty(50));
+
+        // already simplified, keep the input as it was written
+        let in_cost = CostFn.cost_rec(in_expr);
+        if cost >= in_cost {
+            tracing::debug!("input is already simplified, cost={in_cost:?}");
+            // `6/4` costs as much as `3/2` but constants are still reduced
+            let folded = fold_constants(&runner.egraph, in_expr);
+            let root = folded.root.expect("Not evaluated");
+            let split = split_rational_pow(&folded, root, rationalize);
+            return canonical_order(&split, split.root.expect("Not evaluated"));
+        }
+
+        #[cfg(test)]
+        REBUILDS.with(|rebuilds| rebuilds.set(rebuilds.get() + 1));
 
-        YafcExpr {
-            // A hack?:
+        // A hack?:
+        let expr = YafcExpr {
             root: Some((expr.as_ref().len() - 1).into()),
             expr,
+        };
+        // equally cheap forms of a constant like `6/4` and `3/2` are tied
+        let folded = fold_constants(&runner.egraph, &expr);
+        let root = folded.root.expect("Not evaluated");
+        let split = split_rational_pow(&folded, root, rationalize);
+        canonical_order(&split, split.root.expect("Not evaluated"))
+    }
+
+    fn minimize_visible(in_expr: &YafcExpr) -> YafcExpr {
+        let mut expr = in_expr.clone();
+        loop {
+            let runner = Self::runner().with_expr(&expr).run(&*RULES);
+            let extractor = Extractor::new(&runner.egraph, AstSize);
+
+            let (size, best) = extractor.find_best(runner.roots[0]);
+            tracing::debug!("size={size},expr={}", best.pretty(50));
+            if size >= expr.node_count() {
+                return expr;
+            }
+
+            let root = (best.as_ref().len() - 1).into();
+            expr = canonical_order(&best, root);
+        }
+    }
+
+    /// Move everything to the left side of a relation: `a = b` becomes `a - b = 0`
+    ///
+    /// Expressions that aren't relations are just simplified
+    pub fn normalize(in_expr: &YafcExpr) -> YafcExpr {
+        let Some((op, lhs, rhs)) = in_expr.as_relation() else {
+            return Self::run(in_expr);
+        };
+
+        let mut diff = YafcExpr::new();
+        let lhs = diff.copy_from(&lhs, lhs.root.expect("Not evaluated"));
+        let rhs = diff.copy_from(&rhs, rhs.root.expect("Not evaluated"));
+        diff.root = Some(diff.make_sub(lhs, rhs));
+        let diff = Self::run(&diff);
+
+        let mut out = YafcExpr::new();
+        let lhs = out.copy_from(&diff, diff.root.expect("Not evaluated"));
+        let zero = out.make_num(0);
+        out.root = Some(out.make_bin(op, lhs, zero));
+        out
+    }
+
+    /// Simplify and make the coefficient of the first term positive:
+    /// `1 - x` gives `x - 1` and `true` for the sign that was taken out
+    ///
+    /// The input is the result negated if the flag is set, so `1 - x` and `x - 1`
+    /// give the same expression with different flags
+    pub fn canonical_sign(in_expr: &YafcExpr) -> (YafcExpr, bool) {
+        let simplified = Self::run(in_expr);
+        let leading = simplified.as_sum().remove(0).as_product().remove(0);
+        match leading[leading.root.expect("Not evaluated")] {
+            YafcLanguage::Num(num) if num < 0 => (simplified.negate(), true),
+            _ => (simplified, false),
         }
     }
+
+    /// Check if [`Simplifier::run`] would leave the expression as it is
+    pub fn is_simplified(in_expr: &YafcExpr) -> bool {
+        let root = in_expr.root.expect("Not evaluated");
+        let simplified = Self::run(in_expr);
+        // copied so that both have the same node layout
+        simplified.subexpr(simplified.root.expect("Not evaluated")) == in_expr.subexpr(root)
+    }
+
+    /// [`Simplifier::run`] with the rewrites that led to the result, in the order they
+    /// were applied: `x + x` has `add-eq` turning `x + x` into `2 * x`
+    ///
+    /// The steps end at the extracted expression, before its terms are sorted and its
+    /// constants folded. Detours that come back to an earlier expression are left out,
+    /// and so are the reorderings like `commutative-add`, the result is sorted anyway
+    pub fn run_explained(in_expr: &YafcExpr) -> (YafcExpr, Vec<Explanation>) {
+        let simplified = Self::run(in_expr);
+
+        let input = in_expr.subexpr(in_expr.root.expect("Not evaluated"));
+        let mut runner = Self::runner()
+            .with_explanations_enabled()
+            .with_expr(&input)
+            .run(&*RULES);
+        let (_, best) = Extractor::new(&runner.egraph, CostFn).find_best(runner.roots[0]);
+        let mut explanation = runner.explain_equivalence(&input.expr, &best);
+
+        // a step back to an earlier expression drops the steps in between
+        let mut path: Vec<(&FlatTerm<YafcLanguage>, YafcExpr)> = vec![];
+        for term in explanation.make_flat_explanation() {
+            let expr = flat_term_expr(term);
+            match path.iter().position(|(_, other)| *other == expr) {
+                Some(n) => path.truncate(n + 1),
+                None => path.push((term, expr)),
+            }
+        }
+        let explanations = path
+            .windows(2)
+            .filter_map(|step| explain_step(step[0].0, step[1].0))
+            .filter(|step| {
+                !step.rule.starts_with("commutative-") && !step.rule.starts_with("associative-")
+            })
+            .collect();
+        (simplified, explanations)
+    }
+
+    /// Check if both expressions simplify to the same thing
+    pub fn equivalent(lhs: &YafcExpr, rhs: &YafcExpr) -> bool {
+        let runner = Self::runner()
+            .with_expr(lhs)
+            .with_expr(rhs)
+            .with_hook(|runner| {
+                let egraph = &runner.egraph;
+                if egraph.find(runner.roots[0]) == egraph.find(runner.roots[1]) {
+                    Err("equivalent".into())
+                } else {
+                    Ok(())
+                }
+            })
+            .run(&*RULES);
+
+        let egraph = &runner.egraph;
+        egraph.find(runner.roots[0]) == egraph.find(runner.roots[1])
+    }
+
+    fn runner() -> Runner<YafcLanguage, ConstFold> {
+        Runner::default().with_time_limit(Duration::from_millis(100))
+    }
 }
 
 //
 
-static RULES: Lazy<Vec<Rewrite<YafcLanguage, ConstFold>>> = Lazy::new(|| {
+/// Bases of the non constant powers with negative exponents: `x / (y + 1)` gives `y + 1`
+fn divisors(expr: &YafcExpr) -> Vec<YafcExpr> {
+    expr.as_ref()
+        .iter()
+        .filter_map(|node| match node {
+            YafcLanguage::Pow([base, exp])
+                if expr.eval_const_rec(*base).is_none()
+                    && expr
+                        .eval_const_rec(*exp)
+                        .is_some_and(|exp| exp.is_negative()) =>
+            {
+                Some(expr.subexpr(*base))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Drop the branches of piecewise expressions that can't be taken, and replace the
+/// piecewise with its value if the first remaining condition holds
+///
+/// `None` if no condition is constant
+fn select_branches(src: &YafcExpr) -> Option<YafcExpr> {
+    let mut changed = false;
+    let mut dst = YafcExpr::new();
+    let root = select_branches_rec(
+        src,
+        src.root.expect("Not evaluated"),
+        &mut dst,
+        &mut changed,
+    );
+    dst.root = Some(root);
+    changed.then_some(dst)
+}
+
+fn select_branches_rec(src: &YafcExpr, i: Id, dst: &mut YafcExpr, changed: &mut bool) -> Id {
+    let YafcLanguage::Piecewise(args) = &src[i] else {
+        let node = src[i]
+            .clone()
+            .map_children(|child| select_branches_rec(src, child, dst, changed));
+        return dst.add(node);
+    };
+
+    let mut kept = vec![];
+    let mut dropped = false;
+    for branch in args.chunks(2) {
+        match src.eval_const_rec(branch[0]) {
+            Some(holds) if holds.is_zero() => dropped = true,
+            // the later branches are never reached
+            Some(_) => {
+                let value = select_branches_rec(src, branch[1], dst, changed);
+                if kept.is_empty() {
+                    *changed = true;
+                    return value;
+                }
+                *changed |= dropped || kept.len() + 2 < args.len();
+                let holds = dst.make_num(1);
+                kept.extend([holds, value]);
+                return dst.add(YafcLanguage::Piecewise(kept));
+            }
+            None => {
+                for &arg in branch {
+                    kept.push(select_branches_rec(src, arg, dst, changed));
+                }
+            }
+        }
+    }
+    if kept.is_empty() {
+        // no branch holds, left as it was written
+        return dst.copy_from(src, i);
+    }
+    *changed |= dropped;
+    dst.add(YafcLanguage::Piecewise(kept))
+}
+
+/// Replace `opaque(..)` subexpressions with variables that can't be parsed: `#0`, `#1`, ..
+struct Hider<'a, F> {
+    prefix: &'a str,
+    is_hidden: F,
+    hidden: &'a mut Vec<(String, YafcExpr)>,
+}
+
+impl<F: Fn(&YafcLanguage) -> bool> Hider<'_, F> {
+    fn hide(&mut self, src: &YafcExpr, i: Id, dst: &mut YafcExpr) -> Id {
+        if !(self.is_hidden)(&src[i]) {
+            let node = src[i]
+                .clone()
+                .map_children(|child| self.hide(src, child, dst));
+            return dst.add(node);
+        }
+
+        // equal hidden subexpressions share the placeholder
+        let subexpr = src.subexpr(i);
+        let name = match self.hidden.iter().find(|(_, other)| *other == subexpr) {
+            Some((name, _)) => name.clone(),
+            None => {
+                let name = format!("{}{}", self.prefix, self.hidden.len());
+                self.hidden.push((name.clone(), subexpr));
+                name
+            }
+        };
+        dst.make_var(&name)
+    }
+}
+
+/// The rewrite that turns the step `before` into `after`, `after` marks the rewritten node
+fn explain_step(
+    before: &FlatTerm<YafcLanguage>,
+    after: &FlatTerm<YafcLanguage>,
+) -> Option<Explanation> {
+    fn find(term: &FlatTerm<YafcLanguage>, path: &mut Vec<usize>) -> Option<Symbol> {
+        if let Some(rule) = term.forward_rule.or(term.backward_rule) {
+            return Some(rule);
+        }
+        for (n, child) in term.children.iter().enumerate() {
+            path.push(n);
+            if let Some(rule) = find(child, path) {
+                return Some(rule);
+            }
+            path.pop();
+        }
+        None
+    }
+
+    /// The subterms at the variables of `pattern`, `None` if `term` doesn't match it
+    fn bind(
+        pattern: &PatternAst<YafcLanguage>,
+        p: Id,
+        term: &FlatTerm<YafcLanguage>,
+        operands: &mut Vec<YafcExpr>,
+    ) -> Option<()> {
+        match &pattern[p] {
+            ENodeOrVar::Var(_) => operands.push(flat_term_expr(term)),
+            ENodeOrVar::ENode(node) => {
+                if !node.matches(&term.node) {
+                    return None;
+                }
+                for (&p, child) in node.children().iter().zip(&term.children) {
+                    bind(pattern, p, child, operands)?;
+                }
+            }
+        }
+        Some(())
+    }
+
+    let mut path = vec![];
+    let rule = find(after, &mut path)?;
+    let (before, after) = path.iter().fold((before, after), |(before, after), &n| {
+        (&before.children[n], &after.children[n])
+    });
+
+    // a rule used backwards rewrote `after` into `before`
+    let matched = match after.forward_rule {
+        Some(_) => before,
+        None => after,
+    };
+    let mut operands = vec![];
+    let pattern = RULES
+        .iter()
+        .find(|rewrite| rewrite.name == rule)
+        .and_then(|rewrite| rewrite.searcher.get_pattern_ast());
+    if let Some(pattern) = pattern {
+        let root = (pattern.as_ref().len() - 1).into();
+        if bind(pattern, root, matched, &mut operands).is_none() {
+            operands.clear();
+        }
+    }
+
+    Some(Explanation {
+        rule: rule.to_string(),
+        before: flat_term_expr(before),
+        after: flat_term_expr(after),
+        operands,
+    })
+}
+
+fn flat_term_expr(term: &FlatTerm<YafcLanguage>) -> YafcExpr {
+    fn to_expr(term: &FlatTerm<YafcLanguage>, out: &mut YafcExpr) -> Id {
+        let children: Vec<_> = term
+            .children
+            .iter()
+            .map(|child| to_expr(child, out))
+            .collect();
+        let mut children = children.into_iter();
+        let node = term.node.clone().map_children(|_| children.next().unwrap());
+        out.add(node)
+    }
+
+    let mut expr = YafcExpr::new();
+    expr.root = Some(to_expr(term, &mut expr));
+    expr
+}
+
+/// Number of terms in the longest sum of the expression
+fn max_sum_terms(expr: &RecExpr<YafcLanguage>, i: Id) -> usize {
+    let node = &expr[i];
+    let mut operands = vec![];
+    match node {
+        YafcLanguage::Add(_) => node.flatten(expr, i, &mut operands),
+        _ => operands.extend_from_slice(node.children()),
+    }
+
+    let inner = operands
+        .iter()
+        .map(|&operand| max_sum_terms(expr, operand))
+        .max()
+        .unwrap_or(0);
+    match node {
+        YafcLanguage::Add(_) => inner.max(operands.len()),
+        _ => inner,
+    }
+}
+
+/// Add up the terms of every sum that only differ in their numeric coefficient,
+/// `x + 2*x + 3` becomes `3 * x + 3`
+///
+/// The rewrites find these too, but on sums with many terms the e-graph hits its limits
+/// first. Equal subexpressions share an id after hash-consing, so the terms are grouped
+/// by the ids of their factors without comparing or cloning them
+fn combine_like_terms(src: &YafcExpr) -> YafcExpr {
+    fn rec(src: &YafcExpr, i: Id, dst: &mut YafcExpr, memo: &mut HashMap<Id, Id>) -> Id {
+        if let Some(&id) = memo.get(&i) {
+            return id;
+        }
+        let id = match &src[i] {
+            node @ YafcLanguage::Add(_) => {
+                let mut terms = vec![];
+                node.flatten(&src.expr, i, &mut terms);
+
+                // coefficients and the other factors, in the order they first appear
+                let mut combined: Vec<(Num, Vec<Id>)> = vec![];
+                let mut index: HashMap<Vec<Id>, usize> = HashMap::new();
+                for term in terms {
+                    let (coeff, factors) = split_coefficient(src, term);
+                    let n = match index.get(&factors) {
+                        Some(&n) => n,
+                        None => {
+                            combined.push((0, factors.clone()));
+                            index.insert(factors, combined.len() - 1);
+                            combined.len() - 1
+                        }
+                    };
+                    match combined[n].0.checked_add(coeff) {
+                        Some(sum) => combined[n].0 = sum,
+                        // the overflowing term is kept apart
+                        None => {
+                            let factors = combined[n].1.clone();
+                            combined.push((coeff, factors.clone()));
+                            index.insert(factors, combined.len() - 1);
+                        }
+                    }
+                }
+
+                let terms: Vec<Id> = combined
+                    .into_iter()
+                    .filter(|(coeff, _)| *coeff != 0)
+                    .map(|(coeff, factors)| {
+                        let factors: Vec<Id> = factors
+                            .into_iter()
+                            .map(|factor| rec(src, factor, dst, memo))
+                            .collect();
+                        let product = factors
+                            .into_iter()
+                            .reduce(|lhs, rhs| dst.make_mul(lhs, rhs));
+                        match (coeff, product) {
+                            (coeff, None) => dst.make_num(coeff),
+                            (1, Some(product)) => product,
+                            (coeff, Some(product)) => {
+                                let coeff = dst.make_num(coeff);
+                                dst.make_mul(coeff, product)
+                            }
+                        }
+                    })
+                    .collect();
+                match terms.into_iter().reduce(|lhs, rhs| dst.make_add(lhs, rhs)) {
+                    Some(sum) => sum,
+                    None => dst.make_num(0),
+                }
+            }
+            node => {
+                let node = node
+                    .clone()
+                    .map_children(|child| rec(src, child, dst, memo));
+                dst.add(node)
+            }
+        };
+        memo.insert(i, id);
+        id
+    }
+
+    let src = src.hashconsed();
+    let mut dst = YafcExpr::new();
+    let root = rec(
+        &src,
+        src.root.expect("Not evaluated"),
+        &mut dst,
+        &mut HashMap::new(),
+    );
+    dst.root = Some(root);
+    dst
+}
+
+/// The product of the numbers in the term `i` and its other factors: `2 * x * 3` gives
+/// `6` and `[x]`. If the numbers overflow, all factors are kept and the coefficient is `1`
+fn split_coefficient(src: &YafcExpr, i: Id) -> (Num, Vec<Id>) {
+    let node = &src[i];
+    let mut factors = vec![];
+    match node {
+        YafcLanguage::Mul(_) => node.flatten(&src.expr, i, &mut factors),
+        _ => factors.push(i),
+    }
+
+    let mut coeff: Num = 1;
+    let mut rest = vec![];
+    for &factor in &factors {
+        match src[factor] {
+            YafcLanguage::Num(num) => match coeff.checked_mul(num) {
+                Some(product) => coeff = product,
+                None => return (1, factors),
+            },
+            _ => rest.push(factor),
+        }
+    }
+    (coeff, rest)
+}
+
+/// Replace constant subexpressions of `src` with their values from the e-graph
+fn fold_constants(egraph: &EGraph<YafcLanguage, ConstFold>, src: &YafcExpr) -> YafcExpr {
+    /// Returns the id in `dst` and the e-class of the subexpression
+    fn rec(
+        egraph: &EGraph<YafcLanguage, ConstFold>,
+        src: &YafcExpr,
+        i: Id,
+        dst: &mut YafcExpr,
+    ) -> (Id, Option<Id>) {
+        let mut classes = vec![];
+        let node = src[i].clone().map_children(|child| {
+            let (child, class) = rec(egraph, src, child, dst);
+            classes.push(class);
+            child
+        });
+
+        let mut class_node = src[i].clone();
+        let class = match classes.into_iter().collect::<Option<Vec<_>>>() {
+            Some(classes) => {
+                class_node.children_mut().copy_from_slice(&classes);
+                egraph.lookup(class_node)
+            }
+            None => None,
+        };
+
+        if node.is_leaf() {
+            return (dst.add(node), class);
+        }
+        match class.map(|class| (class, egraph[class].data)) {
+            Some((_, Some(num))) => (make_rational(dst, num), class),
+            // `i^3` costs as much as `-i`, so the extractor can keep either
+            Some((class, None)) => match imag_sign(egraph, class) {
+                Some(1) => (dst.add(YafcLanguage::Imag), Some(class)),
+                Some(_) => {
+                    let neg_1 = dst.add(YafcLanguage::Num(-1));
+                    let imag = dst.add(YafcLanguage::Imag);
+                    (dst.add(YafcLanguage::Mul([neg_1, imag])), Some(class))
+                }
+                None => (dst.add(node), Some(class)),
+            },
+            None => (dst.add(node), class),
+        }
+    }
+
+    /// `Some(1)` if the class is `i` and `Some(-1)` if it's `-i`
+    fn imag_sign(egraph: &EGraph<YafcLanguage, ConstFold>, class: Id) -> Option<Num> {
+        let is_imag = |class: Id| {
+            egraph[class]
+                .nodes
+                .iter()
+                .any(|node| matches!(node, YafcLanguage::Imag))
+        };
+        if is_imag(class) {
+            return Some(1);
+        }
+        egraph[class].nodes.iter().find_map(|node| match node {
+            YafcLanguage::Mul([a, b])
+                if egraph[*a].data == Some(Rational::from_integer(-1)) && is_imag(*b) =>
+            {
+                Some(-1)
+            }
+            _ => None,
+        })
+    }
+
+    let mut dst = YafcExpr::new();
+    let (root, _) = rec(egraph, src, src.root.expect("Not evaluated"), &mut dst);
+    dst.root = Some(root);
+    dst
+}
+
+/// Split rational powers into an integer power and a root: `x^(7/2)` becomes `x^3 * x^(1/2)`
+///
+/// Exponents within `-2..2` like `x^(1/2)` and `x^(3/2)` are left as they are.
+/// With `rationalize` negative exponents get a positive root: `x^(-1/2)` becomes `x^(1/2) * x^-1`
+fn split_rational_pow(src: &RecExpr<YafcLanguage>, root: Id, rationalize: bool) -> YafcExpr {
+    fn rec(src: &RecExpr<YafcLanguage>, i: Id, dst: &mut YafcExpr, rationalize: bool) -> Id {
+        match &src[i] {
+            YafcLanguage::Pow([base, exp]) => {
+                let (mut root_base, mut value) = (*base, as_rational(src, *exp));
+                // (x^a)^n = x^(a*n) for whole n, `1/sqrt(x)` is `(x^(1/2))^-1`
+                match (&src[*base], value) {
+                    (YafcLanguage::Pow([inner, inner_exp]), Some(n))
+                        if rationalize && n.is_integer() =>
+                    {
+                        if let Some(a) = as_rational(src, *inner_exp) {
+                            root_base = *inner;
+                            value = a.checked_mul(&n);
+                        }
+                    }
+                    _ => {}
+                }
+
+                let whole = match value {
+                    Some(exp) if exp.is_integer() => None,
+                    Some(exp) if rationalize && exp.is_negative() => Some((exp, exp.floor())),
+                    Some(exp) if exp.abs() > Rational::from_integer(2) => Some((exp, exp.trunc())),
+                    _ => None,
+                };
+                match whole {
+                    Some((exp, whole)) => {
+                        let base = rec(src, root_base, dst, rationalize);
+                        let fract = make_rational(dst, exp - whole);
+                        let whole = match whole.to_integer() {
+                            1 => base,
+                            whole => {
+                                let whole = dst.make_num(whole);
+                                dst.make_pow(base, whole)
+                            }
+                        };
+                        let fract = dst.make_pow(base, fract);
+                        dst.make_mul(whole, fract)
+                    }
+                    None => {
+                        let base = rec(src, *base, dst, rationalize);
+                        let exp = rec(src, *exp, dst, rationalize);
+                        dst.make_pow(base, exp)
+                    }
+                }
+            }
+            node => {
+                let node = node
+                    .clone()
+                    .map_children(|child| rec(src, child, dst, rationalize));
+                dst.add(node)
+            }
+        }
+    }
+
+    let mut dst = YafcExpr::new();
+    let root = rec(src, root, &mut dst, rationalize);
+    dst.root = Some(root);
+    dst
+}
+
+/// The value of a constant in the forms `ConstFold` produces: `n`, `q^-1` and `p * q^-1`
+fn as_rational(expr: &RecExpr<YafcLanguage>, i: Id) -> Option<Rational> {
+    let inv = |i: Id| match &expr[i] {
+        YafcLanguage::Pow([denom, exp]) => match (&expr[*denom], &expr[*exp]) {
+            (YafcLanguage::Num(denom), YafcLanguage::Num(-1)) if *denom != 0 => {
+                Some(Rational::new(1, *denom))
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+
+    match &expr[i] {
+        YafcLanguage::Num(num) => Some(Rational::from_integer(*num)),
+        YafcLanguage::Pow(_) => inv(i),
+        YafcLanguage::Mul([lhs, rhs]) => match (&expr[*lhs], &expr[*rhs]) {
+            (YafcLanguage::Num(numer), _) => Some(inv(*rhs)? * numer),
+            (_, YafcLanguage::Num(numer)) => Some(inv(*lhs)? * numer),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Copy of the expression with the decimals that fit a [`Rational`] written as fractions
+fn floats_to_rationals(src: &YafcExpr) -> YafcExpr {
+    let mut out = YafcExpr::new();
+    let mut ids = vec![];
+    for node in src.as_ref() {
+        let id = match node {
+            YafcLanguage::Float(float) if float.to_rational().is_some() => {
+                make_rational(&mut out, float.to_rational().unwrap())
+            }
+            node => out.add(node.clone().map_children(|child| ids[usize::from(child)])),
+        };
+        ids.push(id);
+    }
+    out.root = src.root.map(|root| ids[usize::from(root)]);
+    out
+}
+
+/// Copy of the expression with the constant subexpressions that aren't integers,
+/// like `1/2` or `3 * 2^-1`, written as decimals
+///
+/// The constant factors of a product are one decimal: `3 * x / 2` is `1.5 * x`
+fn rationals_to_floats(src: &YafcExpr) -> YafcExpr {
+    fn make_float(dst: &mut YafcExpr, value: Rational) -> Id {
+        let float = dst.make_float(value.abs().to_f64().unwrap_or(f64::NAN));
+        if value.is_negative() {
+            dst.make_neg(float)
+        } else {
+            float
+        }
+    }
+
+    fn rec(src: &YafcExpr, values: &[Option<Rational>], i: Id, dst: &mut YafcExpr) -> Id {
+        let value = |i: Id| values[usize::from(i)];
+        let mut factors = vec![];
+        if let YafcLanguage::Mul(_) = src[i] {
+            src[i].flatten(src, i, &mut factors);
+        }
+        let (constants, rest): (Vec<Id>, Vec<Id>) = factors
+            .into_iter()
+            .partition(|&factor| value(factor).is_some());
+        let coefficient = constants.iter().try_fold(Rational::one(), |acc, &factor| {
+            acc.checked_mul(&value(factor)?)
+        });
+
+        match value(i) {
+            Some(value) if !value.is_integer() => make_float(dst, value),
+            _ if !rest.is_empty() && coefficient.is_some_and(|c| !c.is_integer()) => {
+                let coefficient = make_float(dst, coefficient.unwrap());
+                rest.into_iter().fold(coefficient, |acc, factor| {
+                    let factor = rec(src, values, factor, dst);
+                    dst.make_mul(acc, factor)
+                })
+            }
+            _ => {
+                let node = src[i]
+                    .clone()
+                    .map_children(|child| rec(src, values, child, dst));
+                dst.add(node)
+            }
+        }
+    }
+
+    let mut values: Vec<Option<Rational>> = vec![];
+    for node in src.as_ref() {
+        let value = fold_const(node, |child| values[usize::from(child)]);
+        values.push(value);
+    }
+    let mut dst = YafcExpr::new();
+    dst.root = Some(rec(
+        src,
+        &values,
+        src.root.expect("Not evaluated"),
+        &mut dst,
+    ));
+    dst
+}
+
+fn make_rational(dst: &mut YafcExpr, num: Rational) -> Id {
+    let numer = dst.make_num(*num.numer());
+    if num.is_integer() {
+        return numer;
+    }
+    let denom = dst.make_num(*num.denom());
+    if num.numer().is_one() {
+        dst.make_inv(denom)
+    } else {
+        dst.make_div(numer, denom)
+    }
+}
+
+/// Rebuild the expression with the operands of `+` and `*` chains sorted,
+/// so that the output doesn't depend on the order of the input terms
+fn canonical_order(src: &RecExpr<YafcLanguage>, root: Id) -> YafcExpr {
+    canonical_order_by(src, root, &[])
+}
+
+/// [`canonical_order`] with the variables in `var_order` sorted first
+fn canonical_order_by(src: &RecExpr<YafcLanguage>, root: Id, var_order: &[String]) -> YafcExpr {
+    let mut dst = YafcExpr::new();
+    let root = canonical_order_rec(src, root, var_order, &mut dst);
+    dst.root = Some(root);
+    dst
+}
+
+fn canonical_order_rec(
+    src: &RecExpr<YafcLanguage>,
+    i: Id,
+    var_order: &[String],
+    dst: &mut YafcExpr,
+) -> Id {
+    match &src[i] {
+        node @ (YafcLanguage::Add(_)
+        | YafcLanguage::Mul(_)
+        | YafcLanguage::Max(_)
+        | YafcLanguage::Min(_)) => {
+            let mut operands = vec![];
+            node.flatten(src, i, &mut operands);
+
+            let operands: Vec<Id> = operands
+                .into_iter()
+                .map(|operand| canonical_order_rec(src, operand, var_order, dst))
+                .collect();
+            let mut operands = fold_chain_constants(node, operands, dst);
+            if let YafcLanguage::Mul(_) = node {
+                operands = merge_num_powers(operands, var_order, dst);
+            }
+
+            // constants go last in sums and first otherwise: `2 * x + 3`,
+            // divisors go last in products: `2 * x / 3`
+            let is_add = matches!(node, YafcLanguage::Add(_));
+            let group = |i: Id| match &dst[i] {
+                YafcLanguage::Num(_) | YafcLanguage::Float(_) if is_add => 1,
+                YafcLanguage::Num(_) | YafcLanguage::Float(_) => 0,
+                YafcLanguage::Pow([_, exp]) if !is_add && dst[*exp] == YafcLanguage::Num(-1) => 2,
+                _ if is_add => 0,
+                _ => 1,
+            };
+            operands.sort_by(|&a, &b| {
+                group(a)
+                    .cmp(&group(b))
+                    .then_with(|| cmp_rec(dst, a, b, var_order))
+            });
+
+            // a negative coefficient scales the rest of the product: `-2 * (x * y)`,
+            // so that the sign can be printed in front of the term
+            let sign = match (node, operands.as_slice()) {
+                (YafcLanguage::Mul(_), [first, _, _, ..]) if matches!(dst[*first], YafcLanguage::Num(num) if num < 0) => {
+                    Some(operands.remove(0))
+                }
+                _ => None,
+            };
+
+            let chain = operands
+                .into_iter()
+                .reduce(|lhs, rhs| {
+                    let mut node = node.clone();
+                    node.children_mut().copy_from_slice(&[lhs, rhs]);
+                    dst.add(node)
+                })
+                .expect("binary operators always have operands");
+            match sign {
+                Some(sign) => dst.make_mul(sign, chain),
+                None => chain,
+            }
+        }
+        node => {
+            let node = node
+                .clone()
+                .map_children(|child| canonical_order_rec(src, child, var_order, dst));
+            dst.add(node)
+        }
+    }
+}
+
+/// Sort the terms of the simplified sum by where the same term first appears in the original,
+/// ignoring numeric coefficients, new terms go last
+fn stable_order(original: &YafcExpr, simplified: &YafcExpr) -> YafcExpr {
+    // `3 * x` and `x` are the same term, all constants are the same term
+    let like = |term: &YafcExpr| {
+        let factors = term.as_product().into_iter().filter(|factor| {
+            !matches!(
+                factor[factor.root.expect("Not evaluated")],
+                YafcLanguage::Num(_)
+            )
+        });
+        let rest = YafcExpr::from_operands(BinOp::Mul, factors)?;
+        let rest = canonical_order(&rest, rest.root.expect("Not evaluated"));
+        // copied again so that equal terms get the same node layout
+        Some(rest.subexpr(rest.root.expect("Not evaluated")))
+    };
+
+    let original: Vec<_> = original.as_sum().iter().map(like).collect();
+    let mut terms = simplified.as_sum();
+    terms.sort_by_key(|term| {
+        let term = like(term);
+        original
+            .iter()
+            .position(|other| *other == term)
+            .unwrap_or(usize::MAX)
+    });
+
+    YafcExpr::from_operands(BinOp::Add, terms).expect("sums always have terms")
+}
+
+/// Move the factors made of hidden subexpressions in front of the rest of the product,
+/// like numbers are: `x * (#n0 + #n1)` becomes `(#n0 + #n1) * x`
+fn hidden_factors_first(src: &YafcExpr, i: Id, prefix: &str, dst: &mut YafcExpr) -> Id {
+    fn is_hidden(src: &YafcExpr, i: Id, prefix: &str) -> bool {
+        match &src[i] {
+            YafcLanguage::Var(var) => var.as_str().starts_with(prefix),
+            YafcLanguage::Num(_) => true,
+            YafcLanguage::Const(_)
+            | YafcLanguage::Imag
+            | YafcLanguage::Euler
+            | YafcLanguage::Call(..) => false,
+            node => node
+                .children()
+                .iter()
+                .all(|&child| is_hidden(src, child, prefix)),
+        }
+    }
+
+    match &src[i] {
+        node @ YafcLanguage::Mul(_) => {
+            let mut operands = vec![];
+            node.flatten(&src.expr, i, &mut operands);
+
+            // divisors stay last: `x / (#n0 + #n1)`
+            let group = |i: Id| match &src[i] {
+                YafcLanguage::Pow([_, exp]) if src[*exp] == YafcLanguage::Num(-1) => 2,
+                _ if is_hidden(src, i, prefix) => 0,
+                _ => 1,
+            };
+            operands.sort_by_key(|&i| group(i));
+            let operands: Vec<Id> = operands
+                .into_iter()
+                .map(|operand| hidden_factors_first(src, operand, prefix, dst))
+                .collect();
+            operands
+                .into_iter()
+                .reduce(|lhs, rhs| dst.make_mul(lhs, rhs))
+                .unwrap()
+        }
+        node => {
+            let node = node
+                .clone()
+                .map_children(|child| hidden_factors_first(src, child, prefix, dst));
+            dst.add(node)
+        }
+    }
+}
+
+/// Fold the constants of a flattened chain that the extractor left apart: `x * 6 / 4` is `3 * x / 2`
+fn fold_chain_constants(node: &YafcLanguage, operands: Vec<Id>, dst: &mut YafcExpr) -> Vec<Id> {
+    let (constants, mut operands): (Vec<Id>, Vec<Id>) = operands
+        .into_iter()
+        .partition(|&i| as_rational(dst, i).is_some());
+
+    let fold = |a: Rational, b: Rational| match node {
+        YafcLanguage::Add(_) => a.checked_add(&b),
+        YafcLanguage::Mul(_) => a.checked_mul(&b),
+        YafcLanguage::Max(_) => Some(a.max(b)),
+        _ => Some(a.min(b)),
+    };
+    let value = constants
+        .iter()
+        .map(|&i| as_rational(dst, i))
+        .reduce(|a, b| fold(a?, b?));
+    let (Some(Some(value)), true) = (value, constants.len() > 1) else {
+        operands.extend(constants);
+        return operands;
+    };
+
+    let identity = match node {
+        YafcLanguage::Add(_) => value.is_zero(),
+        YafcLanguage::Mul(_) => value.is_one(),
+        _ => false,
+    };
+    if identity && !operands.is_empty() {
+        return operands;
+    }
+
+    match node {
+        // the numerator and the divisor are separate factors of the product
+        YafcLanguage::Mul(_) if !value.is_integer() => {
+            if !value.numer().is_one() || operands.is_empty() {
+                operands.push(dst.make_num(*value.numer()));
+            }
+            let denom = dst.make_num(*value.denom());
+            operands.push(dst.make_inv(denom));
+        }
+        _ => operands.push(make_rational(dst, value)),
+    }
+    operands
+}
+
+/// Fold an integer factor into a power of the same number: `8 * 2^x` is `2^(x + 3)`
+///
+/// `2^3` is already folded into `8`, so `2^3 * 2^x` is written the same way
+fn merge_num_powers(mut operands: Vec<Id>, var_order: &[String], dst: &mut YafcExpr) -> Vec<Id> {
+    let Some((n, num)) = operands
+        .iter()
+        .enumerate()
+        .find_map(|(n, &i)| match dst[i] {
+            YafcLanguage::Num(num) if num.unsigned_abs() > 1 => Some((n, num)),
+            _ => None,
+        })
+    else {
+        return operands;
+    };
+    let Some(abs) = num.checked_abs() else {
+        return operands;
+    };
+
+    let power = operands.iter().enumerate().find_map(|(p, &i)| {
+        let YafcLanguage::Pow([base, exp]) = dst[i] else {
+            return None;
+        };
+        let (YafcLanguage::Num(b), false) = (&dst[base], matches!(dst[exp], YafcLanguage::Num(_)))
+        else {
+            return None;
+        };
+        let log = checked_log(Rational::from_integer(*b), Rational::from_integer(abs))?;
+        log.is_positive().then(|| (p, base, exp, log.to_integer()))
+    });
+    let Some((p, base, exp, log)) = power else {
+        return operands;
+    };
+
+    let mut sum = YafcExpr::new();
+    let lhs = sum.copy_from(dst, exp);
+    let rhs = sum.make_num(log);
+    let root = sum.make_add(lhs, rhs);
+    let sum = canonical_order_by(&sum, root, var_order);
+    let exp = dst.copy_from(&sum, sum.root.expect("Not evaluated"));
+
+    operands[p] = dst.make_pow(base, exp);
+    if num < 0 {
+        operands[n] = dst.make_num(-1);
+    } else {
+        operands.remove(n);
+    }
+    operands
+}
+
+/// Structural total order of two subexpressions
+fn cmp_rec(expr: &RecExpr<YafcLanguage>, a: Id, b: Id, var_order: &[String]) -> Ordering {
+    fn rank(node: &YafcLanguage) -> u8 {
+        match node {
+            YafcLanguage::Num(_) | YafcLanguage::Float(_) => 0,
+            YafcLanguage::Var(_) | YafcLanguage::Const(_) => 1,
+            YafcLanguage::Pow(_) => 2,
+            YafcLanguage::Mul(_) => 3,
+            YafcLanguage::Add(_) => 4,
+            _ => 5,
+        }
+    }
+
+    let (a, b) = (&expr[a], &expr[b]);
+    if std::mem::discriminant(a) != std::mem::discriminant(b) {
+        // the derived order of different operators is their declaration order
+        return rank(a).cmp(&rank(b)).then_with(|| a.cmp(b));
+    }
+
+    match (a, b) {
+        (YafcLanguage::Num(a), YafcLanguage::Num(b)) => a.cmp(b),
+        (YafcLanguage::Var(a), YafcLanguage::Var(b)) => {
+            let priority = |var: &Symbol| {
+                var_order
+                    .iter()
+                    .position(|other| other == var.as_str())
+                    .unwrap_or(usize::MAX)
+            };
+            priority(a)
+                .cmp(&priority(b))
+                .then_with(|| a.as_str().cmp(b.as_str()))
+        }
+        _ => a
+            .children()
+            .iter()
+            .zip(b.children())
+            .map(|(&a, &b)| cmp_rec(expr, a, b, var_order))
+            .find(|ord| ord.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+    }
+}
+
+//
+
+static RULES: Lazy<Vec<Rewrite<YafcLanguage, ConstFold>>> = Lazy::new(rules);
+
+fn rules() -> Vec<Rewrite<YafcLanguage, ConstFold>> {
     let omni_dir = [
         rewrite!("commutative-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
         rewrite!("commutative-mul"; "(* ?a ?b)" => "(* ?b ?a)"),
         rewrite!("mul-0"; "(* ?a 0)" => "0"),
+        rewrite!("pow-0"; "(^ ?a 0)" => "1"),
+        rewrite!("commutative-max"; "(max ?a ?b)" => "(max ?b ?a)"),
+        rewrite!("commutative-min"; "(min ?a ?b)" => "(min ?b ?a)"),
+        // idempotent operators
+        rewrite!("max-eq"; "(max ?a ?a)" => "?a"),
+        rewrite!("min-eq"; "(min ?a ?a)" => "?a"),
+        rewrite!("abs-abs"; "(abs (abs ?a))" => "(abs ?a)"),
+        rewrite!("floor-floor"; "(floor (floor ?a))" => "(floor ?a)"),
+        rewrite!("ceil-ceil"; "(ceil (ceil ?a))" => "(ceil ?a)"),
+        rewrite!("log-base"; "(log ?b ?b)" => "1" if log_base("?b")),
+        rewrite!("log-1"; "(log ?b 1)" => "0" if log_base("?b")),
+        // `x and 1` is `x` only if `x` is a condition, `2 and 1` is `1`
+        rewrite!("and-0"; "(and ?a 0)" => "0"),
+        rewrite!("0-and"; "(and 0 ?a)" => "0"),
+        rewrite!("and-1"; "(and ?a 1)" => "?a" if is_condition("?a")),
+        rewrite!("1-and"; "(and 1 ?a)" => "?a" if is_condition("?a")),
+        rewrite!("or-1"; "(or ?a 1)" => "1"),
+        rewrite!("1-or"; "(or 1 ?a)" => "1"),
+        rewrite!("or-0"; "(or ?a 0)" => "?a" if is_condition("?a")),
+        rewrite!("0-or"; "(or 0 ?a)" => "?a" if is_condition("?a")),
+        // only constant exponents, `x * sqrt(x)` is `x^(3/2)`
+        rewrite!("mul-pow"; "(* ?a (^ ?a ?b))" => "(^ ?a (+ ?b 1))"
+            if combinable_pows("?a", "?b", None)),
+        rewrite!("mul-pows"; "(* (^ ?a ?b) (^ ?a ?c))" => "(^ ?a (+ ?b ?c))"
+            if combinable_pows("?a", "?b", Some("?c"))),
+        // symbolic exponents only cancel out: `x^a * x^-a` is `x^0`
+        rewrite!("mul-pow-neg"; "(* (^ ?a ?b) (^ ?a (* -1 ?b)))" => "(^ ?a 0)"
+            if nonzero("?a")),
+        // `x^a / x^a` divides by `(x^a)^-1`, not by `x^-a`
+        rewrite!("mul-pow-inv"; "(* (^ ?a ?b) (^ (^ ?a ?b) -1))" => "(^ ?a 0)"
+            if nonzero("?a")),
+        // a variable k of `(-1)^(2*k)` is taken to be an integer, like in series
+        rewrite!("pow-neg-1-even"; "(^ -1 (* 2 ?k))" => "1" if integer_or_var("?k")),
+        rewrite!("pow-neg-1-shift"; "(^ -1 (+ (* 2 ?k) ?a))" => "(^ -1 ?a)"
+            if integer_or_var("?k")),
     ];
     let bi_dir = [
         rewrite!("associative-add"; "(+ ?a (+ ?b ?c))" <=> "(+ (+ ?a ?b) ?c)"),
         rewrite!("associative-mul"; "(* ?a (* ?b ?c))" <=> "(* (* ?a ?b) ?c)"),
+        rewrite!("associative-max"; "(max ?a (max ?b ?c))" <=> "(max (max ?a ?b) ?c)"),
+        rewrite!("associative-min"; "(min ?a (min ?b ?c))" <=> "(min (min ?a ?b) ?c)"),
         rewrite!("add-0";  "(+ ?a 0)" <=> "?a"),
         rewrite!("add-eq"; "(+ ?a ?a)" <=> "(* 2 ?a)"),
         rewrite!("mul-1";  "(* ?a 1)" <=> "?a"),
         rewrite!("mul-eq"; "(* ?a ?a)" <=> "(^ ?a 2)"),
+        rewrite!("pow-1";  "(^ ?a 1)" <=> "?a"),
         rewrite!("combine-like-terms"; "(+ (* ?a ?b) (* ?a ?c))" <=> "(* ?a (+ ?b ?c))"),
     ];
+    let custom = [
+        rewrite!("expand-sum"; "(sum ?i ?from ?to ?body)" => {
+            ExpandSum {
+                index: "?i".parse().unwrap(),
+                from: "?from".parse().unwrap(),
+                to: "?to".parse().unwrap(),
+                body: "?body".parse().unwrap(),
+            }
+        }),
+        rewrite!("pow-imag"; "(^ i ?n)" => {
+            ImagPow {
+                exp: "?n".parse().unwrap(),
+            }
+        }),
+    ];
     omni_dir
         .into_iter()
         .chain(bi_dir.into_iter().flatten())
+        .chain(custom)
         .collect()
+}
+
+/// [`RULES`] and the rewrites of [`SimplifyOptions::combinatorics`]
+static COMBINATORICS_RULES: Lazy<Vec<Rewrite<YafcLanguage, ConstFold>>> = Lazy::new(|| {
+    let mut rules = rules();
+    rules.push(rewrite!("fac-quotient"; "(* (! ?a) (^ (! ?b) -1))" => {
+        FacQuotient {
+            a: "?a".parse().unwrap(),
+            b: "?b".parse().unwrap(),
+        }
+    }));
+    rules
 });
 
 //
 
+/// `var` is known to not be `0`: a nonzero constant, a symbol or `i`
+///
+/// A symbol is taken to be nonzero, like `x / x` is `1`
+fn nonzero(var: &str) -> impl Fn(&mut EGraph<YafcLanguage, ConstFold>, Id, &Subst) -> bool {
+    let var: Var = var.parse().unwrap();
+    move |egraph, _, subst| is_nonzero(&egraph[subst[var]])
+}
+
+fn is_nonzero(class: &EClass<YafcLanguage, Option<Rational>>) -> bool {
+    match class.data {
+        Some(num) => !num.is_zero(),
+        None => class.iter().any(|node| {
+            matches!(
+                node,
+                YafcLanguage::Var(_)
+                    | YafcLanguage::Const(_)
+                    | YafcLanguage::Imag
+                    | YafcLanguage::Euler
+            )
+        }),
+    }
+}
+
+/// The constant exponents `b` and `c`, or `1` if there's no `c`, of the powers of `base`
+/// can be added
+///
+/// `0^-1 * 0^1` isn't `0^0`, so exponents that cancel out need a [`nonzero`] base
+fn combinable_pows(
+    base: &str,
+    b: &str,
+    c: Option<&str>,
+) -> impl Fn(&mut EGraph<YafcLanguage, ConstFold>, Id, &Subst) -> bool {
+    let base: Var = base.parse().unwrap();
+    let b: Var = b.parse().unwrap();
+    let c: Option<Var> = c.map(|c| c.parse().unwrap());
+    move |egraph, _, subst| {
+        let exp = |var: Var| egraph[subst[var]].data;
+        let (Some(b), Some(c)) = (exp(b), c.map_or(Some(Rational::one()), exp)) else {
+            return false;
+        };
+        let base = &egraph[subst[base]];
+        if base.data.is_some_and(|num| num.is_zero()) {
+            return false;
+        }
+        b.checked_add(&c).is_none_or(|sum| !sum.is_zero()) || is_nonzero(base)
+    }
+}
+
+/// `var` is `0`, `1`, a relation or a plain variable, something that is true or false
+///
+/// A variable next to `and` or `or` is taken to be a condition, like `k` of
+/// `(-1)^(2*k)` is taken to be an integer. Other expressions like `x + 1` could be `2`,
+/// and `2 or 0` is `1`
+fn is_condition(var: &str) -> impl Fn(&mut EGraph<YafcLanguage, ConstFold>, Id, &Subst) -> bool {
+    let var: Var = var.parse().unwrap();
+    move |egraph, _, subst| {
+        let class = &egraph[subst[var]];
+        match class.data {
+            Some(num) => num.is_zero() || num.is_one(),
+            None => class.iter().any(|node| {
+                node.relation().is_some()
+                    || matches!(
+                        node,
+                        YafcLanguage::And(_) | YafcLanguage::Or(_) | YafcLanguage::Var(_)
+                    )
+            }),
+        }
+    }
+}
+
+/// `var` is an integer or a plain variable
+///
+/// Any other expression could be a fraction in disguise: `x/2` would make
+/// `(-1)^x` equal to `1`
+fn integer_or_var(var: &str) -> impl Fn(&mut EGraph<YafcLanguage, ConstFold>, Id, &Subst) -> bool {
+    let var: Var = var.parse().unwrap();
+    move |egraph, _, subst| {
+        let class = &egraph[subst[var]];
+        match class.data {
+            Some(num) => num.is_integer(),
+            None => class
+                .iter()
+                .any(|node| matches!(node, YafcLanguage::Var(_))),
+        }
+    }
+}
+
+/// `var` isn't a constant that can't be the base of a logarithm, like `1` or `-2`
+fn log_base(var: &str) -> impl Fn(&mut EGraph<YafcLanguage, ConstFold>, Id, &Subst) -> bool {
+    let var: Var = var.parse().unwrap();
+    move |egraph, _, subst| {
+        egraph[subst[var]]
+            .data
+            .is_none_or(|num| num.is_positive() && !num.is_one())
+    }
+}
+
+/// Upper limit for the number of terms a sum with numeric bounds is expanded to
+const MAX_SUM_TERMS: Num = 1000;
+
+/// Expands `sum(i, a, b, body)` into `body[i=a] + ... + body[i=b]`
+/// when both bounds are numbers
+struct ExpandSum {
+    index: Var,
+    from: Var,
+    to: Var,
+    body: Var,
+}
+
+impl Applier<YafcLanguage, ConstFold> for ExpandSum {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<YafcLanguage, ConstFold>,
+        eclass: Id,
+        subst: &Subst,
+        _: Option<&PatternAst<YafcLanguage>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let (Some(from), Some(to)) = (egraph[subst[self.from]].data, egraph[subst[self.to]].data)
+        else {
+            return vec![];
+        };
+        if !from.is_integer() || !to.is_integer() {
+            return vec![];
+        }
+        let (from, to) = (from.to_integer(), to.to_integer());
+        if to.saturating_sub(from) >= MAX_SUM_TERMS {
+            return vec![];
+        }
+        let Some(index) = egraph[subst[self.index]]
+            .iter()
+            .find_map(|node| match node {
+                YafcLanguage::Var(var) => Some(*var),
+                _ => None,
+            })
+        else {
+            return vec![];
+        };
+
+        let body = Extractor::new(egraph, AstSize)
+            .find_best(subst[self.body])
+            .1;
+        let body = YafcExpr {
+            root: Some((body.as_ref().len() - 1).into()),
+            expr: body,
+        };
+
+        let mut sum = egraph.add(YafcLanguage::Num(0));
+        for i in from..=to {
+            let mut value = YafcExpr::new();
+            let root = value.make_num(i);
+            value.root = Some(root);
+
+            let term = egraph.add_expr(&body.substitute(index.as_str(), &value));
+            sum = egraph.add(YafcLanguage::Add([sum, term]));
+        }
+
+        if egraph.union_trusted(eclass, sum, rule_name) {
+            vec![eclass]
+        } else {
+            vec![]
+        }
+    }
+}
+
+const MAX_FAC_TERMS: Num = 16;
+
+/// `a! / b!` is `(b + 1) * .. * a` when `a` is `b` plus a whole number,
+/// and `1 / ((a + 1) * .. * b)` when `a` is the smaller one
+struct FacQuotient {
+    a: Var,
+    b: Var,
+}
+
+impl Applier<YafcLanguage, ConstFold> for FacQuotient {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<YafcLanguage, ConstFold>,
+        eclass: Id,
+        subst: &Subst,
+        _: Option<&PatternAst<YafcLanguage>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let (a, b) = (subst[self.a], subst[self.b]);
+        let Some(offset) = fac_offset(egraph, a, b) else {
+            return vec![];
+        };
+        if offset.abs() > MAX_FAC_TERMS {
+            return vec![];
+        }
+
+        // the terms count up from the smaller of the two
+        let low = if offset < 0 { a } else { b };
+        let mut product = egraph.add(YafcLanguage::Num(1));
+        for k in 1..=offset.abs() {
+            let k = egraph.add(YafcLanguage::Num(k));
+            let term = egraph.add(YafcLanguage::Add([low, k]));
+            product = egraph.add(YafcLanguage::Mul([product, term]));
+        }
+        if offset < 0 {
+            let neg_1 = egraph.add(YafcLanguage::Num(-1));
+            product = egraph.add(YafcLanguage::Pow([product, neg_1]));
+        }
+
+        if egraph.union_trusted(eclass, product, rule_name) {
+            vec![eclass]
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// `a - b` if both classes are the same base plus whole numbers:
+/// `n + 2` and `n - 1` are `3` apart
+fn fac_offset(egraph: &EGraph<YafcLanguage, ConstFold>, a: Id, b: Id) -> Option<Num> {
+    // the ways of writing a class as `base + n`, `base + 0` included
+    let splits = |class: Id| {
+        let added = egraph[class].iter().filter_map(|node| {
+            let YafcLanguage::Add([lhs, rhs]) = node else {
+                return None;
+            };
+            let (base, num) = match (egraph[*lhs].data, egraph[*rhs].data) {
+                (None, Some(num)) => (*lhs, num),
+                (Some(num), None) => (*rhs, num),
+                _ => return None,
+            };
+            num.is_integer()
+                .then(|| (egraph.find(base), num.to_integer()))
+        });
+        added.chain([(egraph.find(class), 0)]).collect::<Vec<_>>()
+    };
+    let b_splits = splits(b);
+    splits(a).into_iter().find_map(|(a_base, a_num)| {
+        b_splits
+            .iter()
+            .find(|(b_base, _)| *b_base == a_base)
+            .and_then(|(_, b_num)| a_num.checked_sub(*b_num))
+    })
+}
+
+/// `i^n` with an integer `n` is one of `1`, `i`, `-1` and `-i`
+struct ImagPow {
+    exp: Var,
+}
+
+impl Applier<YafcLanguage, ConstFold> for ImagPow {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<YafcLanguage, ConstFold>,
+        eclass: Id,
+        subst: &Subst,
+        _: Option<&PatternAst<YafcLanguage>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let Some(exp) = egraph[subst[self.exp]].data.filter(|exp| exp.is_integer()) else {
+            return vec![];
+        };
+        let value = match exp.to_integer().rem_euclid(4) {
+            0 => egraph.add(YafcLanguage::Num(1)),
+            1 => egraph.add(YafcLanguage::Imag),
+            2 => egraph.add(YafcLanguage::Num(-1)),
+            _ => {
+                let neg_1 = egraph.add(YafcLanguage::Num(-1));
+                let imag = egraph.add(YafcLanguage::Imag);
+                egraph.add(YafcLanguage::Mul([neg_1, imag]))
+            }
+        };
+
+        if egraph.union_trusted(eclass, value, rule_name) {
+            vec![eclass]
+        } else {
+            vec![]
+        }
+    }
+}
+
+//
+
 #[derive(Default)]
 struct ConstFold;
 impl Analysis<YafcLanguage> for ConstFold {
-    type Data = Option<Num>;
+    type Data = Option<Rational>;
 
     fn make(egraph: &egg::EGraph<YafcLanguage, Self>, enode: &YafcLanguage) -> Self::Data {
-        let inner = |i: &Id| egraph[*i].data;
-        Some(match enode {
-            YafcLanguage::Num(num) => *num,
-            YafcLanguage::Add([a, b]) => inner(a)?.checked_add(inner(b)?)?,
-            YafcLanguage::Mul([a, b]) => inner(a)?.checked_mul(inner(b)?)?,
-            _ => return None,
-        })
+        fold_const(enode, |i| egraph[i].data)
     }
 
     fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> egg::DidMerge {
@@ -73,24 +1753,432 @@ impl Analysis<YafcLanguage> for ConstFold {
 
     fn modify(egraph: &mut egg::EGraph<YafcLanguage, Self>, id: Id) {
         if let Some(num) = egraph[id].data {
-            let sub = egraph.add(YafcLanguage::Num(num));
-            egraph.union(id, sub);
+            let sub = if num.is_integer() {
+                egraph.add(YafcLanguage::Num(num.to_integer()))
+            } else {
+                // p/q = p * q^-1
+                let numer = egraph.add(YafcLanguage::Num(*num.numer()));
+                let denom = egraph.add(YafcLanguage::Num(*num.denom()));
+                let neg_1 = egraph.add(YafcLanguage::Num(-1));
+                let inv = egraph.add(YafcLanguage::Pow([denom, neg_1]));
+                egraph.add(YafcLanguage::Mul([numer, inv]))
+            };
+            egraph.union_trusted(id, sub, "fold-constants");
         }
     }
 }
 
+/// Value of `enode` from the values of its children
+pub(crate) fn fold_const(
+    enode: &YafcLanguage,
+    inner: impl Fn(Id) -> Option<Rational>,
+) -> Option<Rational> {
+    let inner = |i: &Id| inner(*i);
+    let truth = |b: bool| if b { Rational::one() } else { Rational::zero() };
+    Some(match enode {
+        YafcLanguage::Num(num) => Rational::from_integer(*num),
+        YafcLanguage::Add([a, b]) => inner(a)?.checked_add(&inner(b)?)?,
+        YafcLanguage::Mul([a, b]) => inner(a)?.checked_mul(&inner(b)?)?,
+        YafcLanguage::Max([a, b]) => inner(a)?.max(inner(b)?),
+        YafcLanguage::Min([a, b]) => inner(a)?.min(inner(b)?),
+        // relations are 1 if they hold and 0 otherwise
+        YafcLanguage::Eq([a, b]) => truth(inner(a)? == inner(b)?),
+        YafcLanguage::Lt([a, b]) => truth(inner(a)? < inner(b)?),
+        YafcLanguage::Le([a, b]) => truth(inner(a)? <= inner(b)?),
+        YafcLanguage::Gt([a, b]) => truth(inner(a)? > inner(b)?),
+        YafcLanguage::Ge([a, b]) => truth(inner(a)? >= inner(b)?),
+        YafcLanguage::And([a, b]) => truth(!inner(a)?.is_zero() && !inner(b)?.is_zero()),
+        YafcLanguage::Or([a, b]) => truth(!inner(a)?.is_zero() || !inner(b)?.is_zero()),
+        YafcLanguage::Pow([a, b]) => checked_pow(inner(a)?, inner(b)?)?,
+        YafcLanguage::Log([a, b]) => checked_log(inner(a)?, inner(b)?)?,
+        YafcLanguage::Fac(a) => factorial(inner(a)?)?,
+        YafcLanguage::DoubleFac(a) => double_factorial(inner(a)?)?,
+        YafcLanguage::Floor(a) => inner(a)?.floor(),
+        YafcLanguage::Ceil(a) => inner(a)?.ceil(),
+        YafcLanguage::Abs(a) => inner(a)?.abs(),
+        YafcLanguage::Sin(a) | YafcLanguage::Tan(a) if inner(a)?.is_zero() => Rational::zero(),
+        YafcLanguage::Cos(a) if inner(a)?.is_zero() => Rational::one(),
+        _ => return None,
+    })
+}
+
+/// `n * (n-1) * .. * 1` if `n` is a natural number and the result fits
+fn factorial(n: Rational) -> Option<Rational> {
+    if !n.is_integer() || n.is_negative() {
+        return None;
+    }
+    // overflows past 20!, so the loop stays short
+    (1..=n.to_integer())
+        .try_fold(1 as Num, |acc, k| acc.checked_mul(k))
+        .map(Rational::from_integer)
+}
+
+/// `n * (n-2) * (n-4) * ..` if `n` is a natural number and the result fits
+fn double_factorial(n: Rational) -> Option<Rational> {
+    if !n.is_integer() || n.is_negative() {
+        return None;
+    }
+    // overflows past 33!!, so the loop stays short
+    (1..=n.to_integer())
+        .rev()
+        .step_by(2)
+        .try_fold(1 as Num, |acc, k| acc.checked_mul(k))
+        .map(Rational::from_integer)
+}
+
+/// `base^exp` if the exponent is an integer and the result fits
+fn checked_pow(base: Rational, exp: Rational) -> Option<Rational> {
+    if !exp.is_integer() {
+        return None;
+    }
+    let exp = exp.to_integer();
+    if exp < 0 && base.is_zero() {
+        return None;
+    }
+
+    let abs: u32 = exp.unsigned_abs().try_into().ok()?;
+    let numer = base.numer().checked_pow(abs)?;
+    let denom = base.denom().checked_pow(abs)?;
+    Some(if exp < 0 {
+        Rational::new(denom, numer)
+    } else {
+        Rational::new(numer, denom)
+    })
+}
+
+/// `log_base(arg)` if it is an integer: `log_2(8)` is `3` and `log_2(1/8)` is `-3`
+fn checked_log(base: Rational, arg: Rational) -> Option<Rational> {
+    if !base.is_positive() || base.is_one() || !arg.is_positive() {
+        return None;
+    }
+    // log_b(x) = -log_(1/b)(x) = -log_b(1/x)
+    let (base, sign) = if base < Rational::one() {
+        (base.recip(), -1)
+    } else {
+        (base, 1)
+    };
+    let (arg, sign) = if arg < Rational::one() {
+        (arg.recip(), -sign)
+    } else {
+        (arg, sign)
+    };
+
+    let mut power = Rational::one();
+    let mut log: Num = 0;
+    while power < arg {
+        power = power.checked_mul(&base)?;
+        log += 1;
+    }
+    (power == arg).then(|| Rational::from_integer(sign * log))
+}
+
 struct CostFn;
 impl CostFunction<YafcLanguage> for CostFn {
-    type Cost = usize;
+    /// weighted node count and whether the subexpression is free of variables
+    type Cost = (usize, bool);
 
     fn cost<C>(&mut self, enode: &YafcLanguage, mut costs: C) -> Self::Cost
     where
         C: FnMut(Id) -> Self::Cost,
     {
+        let constant = match enode {
+            YafcLanguage::Var(_) | YafcLanguage::Const(_) => false,
+            _ => enode.all(|i| costs(i).1),
+        };
         let op_cost = match enode {
-            YafcLanguage::Var(_) => 2,
+            YafcLanguage::Var(_) | YafcLanguage::Const(_) => 2,
+            // prefer the folded `9/4` over `2 + 1/4`
+            YafcLanguage::Add([a, b]) if costs(*a).1 && costs(*b).1 => 3,
             _ => 1,
         };
-        enode.fold(op_cost, |sum, i| sum + costs(i))
+        (enode.fold(op_cost, |sum, i| sum + costs(i).0), constant)
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::{NumberPreference, Simplifier, SimplifyOptions};
+    use crate::{ast::YafcExpr, simplify_str};
+
+    fn simplify_opt(s: &str, options: SimplifyOptions) -> String {
+        let expr = YafcExpr::parse_infix(s).unwrap();
+        format!("{:#}", Simplifier::run_opt(&expr, options))
+    }
+
+    #[test]
+    fn division_by_zero_stays() {
+        assert_eq!(simplify_str("1/0").unwrap(), "1 / 0");
+        assert_eq!(simplify_str("x/0").unwrap(), "x / 0");
+        assert_eq!(simplify_str("1/(x-x)").unwrap(), "1 / 0");
+    }
+
+    #[test]
+    fn constant_powers_combine() {
+        assert_eq!(simplify_str("x*sqrt(x)").unwrap(), "x ^ (3 / 2)");
+        assert_eq!(simplify_str("x/x").unwrap(), "1");
+    }
+
+    #[test]
+    fn parenthesized_conditions() {
+        assert_eq!(simplify_str("(x < 1) or 0").unwrap(), "x < 1");
+        assert_eq!(simplify_str("(a = b) and 1").unwrap(), "a = b");
+        assert_eq!(simplify_str("(1 < 2) or (x < 1)").unwrap(), "1");
+    }
+
+    #[test]
+    fn structural_only_keeps_literals() {
+        let structural = |s| simplify_opt(s, SimplifyOptions::structural_only());
+        assert_eq!(structural("2 + 3"), "2 + 3");
+        assert_eq!(structural("x + x"), "2 * x");
+        assert_eq!(structural("2*x + 3*x"), "(2 + 3) * x");
+        assert_eq!(structural("x - x"), "0");
+    }
+
+    #[test]
+    fn explanation_names_the_combined_terms() {
+        let (simplified, steps) =
+            Simplifier::run_explained(&YafcExpr::parse_infix("x + x").unwrap());
+        assert_eq!(format!("{simplified:#}"), "2 * x");
+        let steps: Vec<String> = steps.iter().map(ToString::to_string).collect();
+        assert_eq!(steps, ["add-eq on `x` and `x`: `x + x` became `2 * x`"]);
+    }
+
+    #[test]
+    fn explanation_skips_reorderings() {
+        let (_, steps) = Simplifier::run_explained(&YafcExpr::parse_infix("2*x + 3*x").unwrap());
+        assert!(steps
+            .iter()
+            .all(|step| !step.rule.starts_with("commutative-")));
+        assert!(steps.iter().any(|step| step.rule == "combine-like-terms"));
+    }
+
+    #[test]
+    fn trace_serializes_to_json() {
+        let (_, steps) = Simplifier::run_explained(&YafcExpr::parse_infix("x*x*x").unwrap());
+        assert_eq!(steps.len(), 3);
+        let json = super::Explanation::trace_to_json(&steps);
+        assert!(json.starts_with(
+            r#"[{"rule": "mul-eq", "before": {"op": "*", "args": [{"var": "x"}, {"var": "x"}]}"#
+        ));
+        assert!(json.contains(r#""after": {"op": "^", "args": [{"var": "x"}, {"num": 2}]}"#));
+        assert!(json.ends_with(r#""after": {"num": 3}, "operands": []}]"#));
+    }
+
+    #[test]
+    fn operands_are_sorted() {
+        assert_eq!(simplify_str("y + x + 2").unwrap(), "x + y + 2");
+        assert_eq!(simplify_str("y*3*x").unwrap(), "3 * x * y");
+        assert_eq!(simplify_str("b*a + a*b*2").unwrap(), "3 * a * b");
+    }
+
+    #[test]
+    fn powers_are_right_associative() {
+        assert_eq!(simplify_str("2^3^2").unwrap(), "512");
+        assert_eq!(simplify_str("(2^3)^2").unwrap(), "64");
+        assert_eq!(simplify_str("2^-1").unwrap(), "1 / 2");
+    }
+
+    #[test]
+    fn sums_with_numeric_bounds_expand() {
+        assert_eq!(simplify_str("sum(k, 1, 4, k)").unwrap(), "10");
+        assert_eq!(simplify_str("sum(k, 1, 3, k^2)").unwrap(), "14");
+        assert_eq!(simplify_str("sum(i, 1, 3, i)").unwrap(), "6");
+        assert_eq!(simplify_str("sum(k, 1, n, k)").unwrap(), "sum(k, 1, n, k)");
+    }
+
+    #[test]
+    fn floor_and_ceil_fold() {
+        assert_eq!(simplify_str("floor(7/2)").unwrap(), "3");
+        assert_eq!(simplify_str("ceil(7/2)").unwrap(), "4");
+        assert_eq!(simplify_str("floor(-7/2)").unwrap(), "-4");
+        assert_eq!(simplify_str("floor(x)").unwrap(), "floor(x)");
+    }
+
+    #[test]
+    fn constant_fractions_reduce() {
+        assert_eq!(simplify_str("6/4").unwrap(), "3 / 2");
+        assert_eq!(simplify_str("2/4 + 1/4").unwrap(), "3 / 4");
+        assert_eq!(simplify_str("1/3 + 2/3").unwrap(), "1");
+    }
+
+    #[test]
+    fn factorials_fold() {
+        assert_eq!(simplify_str("5!!").unwrap(), "15");
+        assert_eq!(simplify_str("6!!").unwrap(), "48");
+        assert_eq!(simplify_str("0!!").unwrap(), "1");
+        assert_eq!(simplify_str("4!").unwrap(), "24");
+    }
+
+    #[test]
+    fn chained_relations_fold() {
+        assert_eq!(simplify_str("1<2<3").unwrap(), "1");
+        assert_eq!(simplify_str("1<3<2").unwrap(), "0");
+        assert_eq!(simplify_str("2 >= 3").unwrap(), "0");
+        assert_eq!(simplify_str("x<y<z").unwrap(), "x < y < z");
+    }
+
+    #[test]
+    fn logarithms_fold() {
+        assert_eq!(simplify_str("log(2,8)").unwrap(), "3");
+        assert_eq!(simplify_str("log(3, 1/9)").unwrap(), "-2");
+        assert_eq!(simplify_str("log(x, x)").unwrap(), "1");
+        assert_eq!(simplify_str("log(2, 3)").unwrap(), "log(2, 3)");
+    }
+
+    #[test]
+    fn powers_of_the_imaginary_unit() {
+        assert_eq!(simplify_str("i^2").unwrap(), "-1");
+        assert_eq!(simplify_str("i^3").unwrap(), "-i");
+        assert_eq!(simplify_str("i^4").unwrap(), "1");
+        assert_eq!(simplify_str("i^-1").unwrap(), "-i");
+        assert_eq!(simplify_str("i*i").unwrap(), "-1");
+    }
+
+    #[test]
+    fn long_sums_combine_like_terms() {
+        let terms: Vec<String> = (1..=30).map(|n| format!("{n}*x")).collect();
+        assert_eq!(simplify_str(&terms.join(" + ")).unwrap(), "465 * x");
+        let terms = vec!["x"; 30];
+        assert_eq!(simplify_str(&terms.join(" + ")).unwrap(), "30 * x");
+        assert_eq!(simplify_str("x*2*y + 3*x*y").unwrap(), "5 * x * y");
+        assert_eq!(simplify_str("x + 2*x + 3").unwrap(), "3 * x + 3");
+    }
+
+    #[test]
+    fn common_factors_are_pulled_out() {
+        assert_eq!(simplify_str("x*a + x*b").unwrap(), "x * (a + b)");
+        assert_eq!(simplify_str("x*a + y*b").unwrap(), "a * x + b * y");
+    }
+
+    #[test]
+    fn overflowing_factors_keep_the_variable() {
+        assert_eq!(
+            simplify_str("10000000000 * x * 10000000000").unwrap(),
+            "10000000000 * 10000000000 * x"
+        );
+        assert_eq!(
+            simplify_str("1000000 * x * 1000000").unwrap(),
+            "1000000000000 * x"
+        );
+    }
+
+    #[test]
+    fn signs_of_factors_fold() {
+        assert_eq!(simplify_str("(-x)*(-y)").unwrap(), "x * y");
+        assert_eq!(simplify_str("(-x)*y").unwrap(), "-x * y");
+        assert_eq!(simplify_str("(-x)*(-y)*(-z)").unwrap(), "-x * y * z");
+        assert_eq!(simplify_str("(-2)*x*(-3)").unwrap(), "6 * x");
+    }
+
+    #[test]
+    fn numeric_factors_lead() {
+        assert_eq!(simplify_str("x * 2 * y").unwrap(), "2 * x * y");
+        assert_eq!(simplify_str("x*y*3*z").unwrap(), "3 * x * y * z");
+        assert_eq!(simplify_str("x * 2 * y * 3").unwrap(), "6 * x * y");
+    }
+
+    #[test]
+    fn negations_cancel_coefficients() {
+        assert_eq!(simplify_str("x + -x").unwrap(), "0");
+        assert_eq!(simplify_str("x + (-1)*x").unwrap(), "0");
+        assert_eq!(simplify_str("-x + 2*x").unwrap(), "x");
+        assert_eq!(simplify_str("-(x + y) + y").unwrap(), "-x");
+    }
+
+    #[test]
+    fn reordered_negated_products_cancel() {
+        assert_eq!(simplify_str("a*b + (-1)*b*a").unwrap(), "0");
+        assert_eq!(simplify_str("a*b*c - c*a*b").unwrap(), "0");
+        assert_eq!(simplify_str("a*b - b*a + c").unwrap(), "c");
+    }
+
+    #[test]
+    fn symbolic_coefficients_collect() {
+        assert_eq!(
+            simplify_str("(a+1)*x + (b+2)*x").unwrap(),
+            "x * (a + b + 3)"
+        );
+        assert_eq!(simplify_str("a*x + b*x").unwrap(), "x * (a + b)");
+    }
+
+    #[test]
+    fn symbolic_powers_cancel() {
+        assert_eq!(simplify_str("x^a/x^a").unwrap(), "1");
+        assert_eq!(simplify_str("x^a * x^-a").unwrap(), "1");
+        assert_eq!(simplify_str("0^a/0^a").unwrap(), "0 ^ a / 0 ^ a");
+    }
+
+    #[test]
+    fn logic_identities() {
+        assert_eq!(simplify_str("x and 1").unwrap(), "x");
+        assert_eq!(simplify_str("1 and x").unwrap(), "x");
+        assert_eq!(simplify_str("x or 0").unwrap(), "x");
+        assert_eq!(simplify_str("x and 0").unwrap(), "0");
+        assert_eq!(simplify_str("x or 1").unwrap(), "1");
+        assert_eq!(simplify_str("1 and 0").unwrap(), "0");
+        assert_eq!(
+            simplify_str("(x < 1 or y > 2) and 1").unwrap(),
+            "x < 1 or y > 2"
+        );
+        // `x + 1` could be `2`, which isn't a truth value
+        assert_eq!(simplify_str("(x + 1) or 0").unwrap(), "x + 1 or 0");
+        assert_eq!(simplify_str("2 and 1").unwrap(), "1");
+    }
+
+    #[test]
+    fn batches_match_single_runs() {
+        let exprs: Vec<YafcExpr> = (0..20)
+            .map(|i| YafcExpr::parse_infix(&format!("x*{i} + x - {i}/2")).unwrap())
+            .collect();
+        let batch: Vec<String> = Simplifier::run_batch(&exprs)
+            .iter()
+            .map(|e| format!("{e:#}"))
+            .collect();
+        let single: Vec<String> = exprs
+            .iter()
+            .map(|e| format!("{:#}", Simplifier::run(e)))
+            .collect();
+        assert_eq!(batch, single);
+        assert!(Simplifier::run_batch(&[]).is_empty());
+    }
+
+    #[test]
+    fn number_preference_decides_decimals() {
+        let rational = SimplifyOptions {
+            prefer: NumberPreference::Rational,
+            ..<_>::default()
+        };
+        assert_eq!(simplify_str("0.25 + 1/4").unwrap(), "0.5");
+        assert_eq!(simplify_opt("0.25 + 1/4", rational.clone()), "1 / 2");
+        assert_eq!(simplify_str("0.1 + 0.2").unwrap(), "0.3");
+        assert_eq!(simplify_opt("0.1 + 0.2", rational.clone()), "3 / 10");
+        assert_eq!(simplify_str("x/2 + 2 - 0.5").unwrap(), "0.5 * (x + 3)");
+        assert_eq!(
+            simplify_opt("x/2 + 2 - 0.5", rational.clone()),
+            "(x + 3) / 2"
+        );
+        assert_eq!(simplify_str("0.5*x + x/2").unwrap(), "x");
+        assert_eq!(simplify_str("2.0 * x").unwrap(), "2 * x");
+
+        // without decimals in the input the fractions stay
+        assert_eq!(simplify_str("1/4 + 1/4").unwrap(), "1 / 2");
+        assert_eq!(simplify_opt("1/4 + 1/4", rational), "1 / 2");
+    }
+
+    #[test]
+    fn simplified_input_is_not_rebuilt() {
+        let rebuilds = || super::REBUILDS.with(|rebuilds| rebuilds.get());
+        let expr = YafcExpr::parse_infix("x * y / x + 1").unwrap();
+
+        let before = rebuilds();
+        let once = Simplifier::run(&expr);
+        assert!(rebuilds() > before);
+
+        let before = rebuilds();
+        let twice = Simplifier::run(&once);
+        assert_eq!(rebuilds(), before);
+        assert_eq!(format!("{twice:#}"), format!("{once:#}"));
     }
 }
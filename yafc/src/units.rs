@@ -0,0 +1,197 @@
+use crate::ast::{Num, Rational, YafcExpr, YafcLanguage};
+use egg::Id;
+use num_traits::CheckedMul;
+use std::fmt;
+
+//
+
+/// Exponents of the base dimensions, `m/s^2` is length 1 and time -2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Unit {
+    pub length: Num,
+    pub mass: Num,
+    pub time: Num,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitError {
+    /// Added, compared or maxed quantities with different units: `3 m + 2 s`
+    Mismatch(Unit, Unit),
+
+    /// A quantity with a unit where only plain numbers make sense: `sin(2 m)`
+    NotDimensionless(Unit),
+
+    /// A quantity with a unit raised to a power that doesn't give whole exponents: `m^x`
+    NonIntegerPower,
+}
+
+impl Unit {
+    pub const NONE: Unit = Unit {
+        length: 0,
+        mass: 0,
+        time: 0,
+    };
+
+    /// The unit of a variable name: `m` is a meter, `kg` a kilogram and `s` a second
+    pub fn from_symbol(symbol: &str) -> Option<Unit> {
+        Some(match symbol {
+            "m" => Unit {
+                length: 1,
+                ..Unit::NONE
+            },
+            "kg" => Unit {
+                mass: 1,
+                ..Unit::NONE
+            },
+            "s" => Unit {
+                time: 1,
+                ..Unit::NONE
+            },
+            _ => return None,
+        })
+    }
+
+    pub fn is_none(self) -> bool {
+        self == Unit::NONE
+    }
+
+    fn mul(self, rhs: Unit) -> Unit {
+        Unit {
+            length: self.length + rhs.length,
+            mass: self.mass + rhs.mass,
+            time: self.time + rhs.time,
+        }
+    }
+
+    /// `sqrt(m^2)` is `m`, but `sqrt(m)` has no unit
+    fn pow(self, exp: Rational) -> Option<Unit> {
+        let pow = |dim: Num| {
+            let dim = Rational::from_integer(dim).checked_mul(&exp)?;
+            dim.is_integer().then(|| dim.to_integer())
+        };
+        Some(Unit {
+            length: pow(self.length)?,
+            mass: pow(self.mass)?,
+            time: pow(self.time)?,
+        })
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_none() {
+            return write!(f, "1");
+        }
+        let mut first = true;
+        for (symbol, exp) in [("m", self.length), ("kg", self.mass), ("s", self.time)] {
+            if exp == 0 {
+                continue;
+            }
+            if !first {
+                write!(f, " ")?;
+            }
+            first = false;
+            match exp {
+                1 => write!(f, "{symbol}")?,
+                _ => write!(f, "{symbol}^{exp}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnitError::Mismatch(lhs, rhs) => write!(f, "Incompatible units `{lhs}` and `{rhs}`"),
+            UnitError::NotDimensionless(unit) => write!(f, "Expected a plain number, got `{unit}`"),
+            UnitError::NonIntegerPower => write!(f, "Units can only have whole exponents"),
+        }
+    }
+}
+
+impl std::error::Error for UnitError {}
+
+impl YafcExpr {
+    /// The unit of the expression, the variables `m`, `kg` and `s` are units
+    /// and the rest are plain numbers
+    ///
+    /// `(3 m) * (2 m)` is `m^2` and `3 m + 2 s` is an error. The simplifier
+    /// treats units like any other variable, so it works on the simplified form too
+    pub fn unit(&self) -> Result<Unit, UnitError> {
+        self.unit_rec(self.root.expect("Not evaluated"))
+    }
+
+    fn unit_rec(&self, i: Id) -> Result<Unit, UnitError> {
+        use YafcLanguage::*;
+
+        let same = |a: &Id, b: &Id| {
+            let (a, b) = (self.unit_rec(*a)?, self.unit_rec(*b)?);
+            if a == b {
+                Ok(a)
+            } else {
+                Err(UnitError::Mismatch(a, b))
+            }
+        };
+        let none = |a: &Id| match self.unit_rec(*a)? {
+            unit if unit.is_none() => Ok(Unit::NONE),
+            unit => Err(UnitError::NotDimensionless(unit)),
+        };
+
+        match &self[i] {
+            Num(_) | Float(_) | Const(_) | Imag | Euler => Ok(Unit::NONE),
+            Var(var) => Ok(Unit::from_symbol(var.as_str()).unwrap_or(Unit::NONE)),
+            Add([a, b]) | Max([a, b]) | Min([a, b]) => same(a, b),
+            Eq([a, b]) | Lt([a, b]) | Le([a, b]) | Gt([a, b]) | Ge([a, b]) => {
+                same(a, b).map(|_| Unit::NONE)
+            }
+            And([a, b]) | Or([a, b]) | Log([a, b]) => none(a).and(none(b)),
+            Mul([a, b]) => Ok(self.unit_rec(*a)?.mul(self.unit_rec(*b)?)),
+            Pow([base, exp]) => {
+                none(exp)?;
+                let base = self.unit_rec(*base)?;
+                if base.is_none() {
+                    return Ok(Unit::NONE);
+                }
+                let exp = self
+                    .eval_const_rec(*exp)
+                    .ok_or(UnitError::NonIntegerPower)?;
+                base.pow(exp).ok_or(UnitError::NonIntegerPower)
+            }
+            Floor(a) | Ceil(a) | Abs(a) | Opaque(a) => self.unit_rec(*a),
+            Fac(a) | DoubleFac(a) | Sin(a) | Cos(a) | Tan(a) => none(a),
+            Sum([_, from, to, body]) => {
+                none(from)?;
+                none(to)?;
+                self.unit_rec(*body)
+            }
+            Call(_, args) => args
+                .iter()
+                .try_for_each(|arg| none(arg).map(drop))
+                .map(|_| Unit::NONE),
+            // the branches all have the same unit
+            Piecewise(args) => {
+                let unit = self.unit_rec(args[1])?;
+                for branch in args.chunks(2) {
+                    self.unit_rec(branch[0])?;
+                    match self.unit_rec(branch[1])? {
+                        other if other == unit => {}
+                        other => return Err(UnitError::Mismatch(unit, other)),
+                    }
+                }
+                Ok(unit)
+            }
+            // the elements of a matrix all have the unit of the matrix
+            Matrix(items) | Row(items) => {
+                let unit = self.unit_rec(items[0])?;
+                items[1..]
+                    .iter()
+                    .try_for_each(|item| match self.unit_rec(*item)? {
+                        other if other == unit => Ok(()),
+                        other => Err(UnitError::Mismatch(unit, other)),
+                    })?;
+                Ok(unit)
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::ast::{YafcExpr, YafcLanguage};
+use egg::{Id, Language};
+use std::fmt;
+
+//
+
+/// A node the parser would never build, the operands of the fixed arity operators
+/// like `^` are checked by their types already
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// `f()`, calls need at least one argument
+    EmptyCall(Id),
+
+    /// A matrix without rows or a row without elements
+    EmptyMatrix(Id),
+
+    /// A matrix with rows of different lengths
+    RaggedMatrix(Id),
+
+    /// A row outside of a matrix, or a matrix with something else than rows in it
+    MisplacedRow(Id),
+
+    /// The index of a sum isn't a variable
+    SumIndex(Id),
+
+    /// A piecewise without branches, or with a condition without a value
+    UnpairedBranch(Id),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::EmptyCall(i) => write!(f, "Call without arguments at node {i}"),
+            ValidationError::EmptyMatrix(i) => write!(f, "Empty matrix at node {i}"),
+            ValidationError::RaggedMatrix(i) => {
+                write!(f, "Rows of different lengths in the matrix at node {i}")
+            }
+            ValidationError::MisplacedRow(i) => write!(f, "Row outside of a matrix at node {i}"),
+            ValidationError::UnpairedBranch(i) => {
+                write!(f, "Condition without a value in the piecewise at node {i}")
+            }
+            ValidationError::SumIndex(i) => {
+                write!(f, "The index of the sum at node {i} isn't a variable")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl YafcExpr {
+    /// Check the nodes for shapes the parser would never build, like a ragged matrix,
+    /// and report the first one from the root down
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.validate_rec(self.root.expect("Not evaluated"), false)
+    }
+
+    fn validate_rec(&self, i: Id, in_matrix: bool) -> Result<(), ValidationError> {
+        use YafcLanguage::*;
+
+        let is_row = |row: &Id| matches!(self[*row], Row(_));
+        match &self[i] {
+            Call(_, args) if args.is_empty() => return Err(ValidationError::EmptyCall(i)),
+            Matrix(rows) => {
+                if !rows.iter().all(is_row) {
+                    return Err(ValidationError::MisplacedRow(i));
+                }
+                let Some(first) = rows.first() else {
+                    return Err(ValidationError::EmptyMatrix(i));
+                };
+                let cols = self[*first].len();
+                if cols == 0 {
+                    return Err(ValidationError::EmptyMatrix(i));
+                }
+                if rows.iter().any(|row| self[*row].len() != cols) {
+                    return Err(ValidationError::RaggedMatrix(i));
+                }
+            }
+            Piecewise(args) if args.is_empty() || !args.len().is_multiple_of(2) => {
+                return Err(ValidationError::UnpairedBranch(i))
+            }
+            Row(_) if !in_matrix => return Err(ValidationError::MisplacedRow(i)),
+            Sum([index, ..]) if !matches!(self[*index], Var(_)) => {
+                return Err(ValidationError::SumIndex(i))
+            }
+            _ => {}
+        }
+
+        let in_matrix = matches!(self[i], Matrix(_));
+        self[i]
+            .children()
+            .iter()
+            .try_for_each(|child| self.validate_rec(*child, in_matrix))
+    }
+}
@@ -24,6 +24,11 @@ pub struct CliArgs {
     #[clap(long, value_parser)]
     pub latex_out: bool,
 
+    /// Print the expression trees before and
+    /// after simplification in Graphviz DOT
+    #[clap(long, value_parser)]
+    pub dot: bool,
+
     /// Output in latex
     #[clap(long, value_parser)]
     pub latex_in: bool,
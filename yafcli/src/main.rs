@@ -2,10 +2,7 @@ use clap::Parser;
 use cli::CliArgs;
 use rustyline::{error::ReadlineError, Editor};
 use std::io;
-use yafc::{
-    ast::{PrettyStyle, YafcExpr},
-    simplifier::Simplifier,
-};
+use yafc::{ast::PrettyStyle, define::Definitions, simplifier::Simplifier};
 
 //
 
@@ -17,20 +14,22 @@ fn main() {
     let cli: CliArgs = CliArgs::parse();
 
     tracing_subscriber::fmt::init();
+
+    let mut defs = Definitions::new();
     // env_logger::builder()
     //     .parse_default_env()
     //     .filter(Some("rustyline"), LevelFilter::Error)
     //     .init();
 
     if let Some(line) = &cli.direct {
-        run_line(line, &cli, 0);
+        run_line(line, &cli, &mut defs, 0);
     } else if atty::isnt(atty::Stream::Stdin) {
         // if STDIN is piped
         io::stdin()
             .lines()
             .enumerate()
             .for_each(|(i, line)| match line {
-                Ok(line) => run_line(&line, &cli, i),
+                Ok(line) => run_line(&line, &cli, &mut defs, i),
                 Err(err) => eprintln!("{err}"),
             });
     } else {
@@ -40,7 +39,7 @@ fn main() {
             match rl.readline("in: ") {
                 Ok(line) => {
                     rl.add_history_entry(&line);
-                    run_line(&line, &cli, i);
+                    run_line(&line, &cli, &mut defs, i);
                 }
                 Err(ReadlineError::Interrupted | ReadlineError::Eof) => {
                     break;
@@ -55,10 +54,27 @@ fn main() {
     }
 }
 
-fn run_line(line: &str, cli: &CliArgs, i: usize) {
-    match YafcExpr::parse_infix(line) {
+fn run_line(line: &str, cli: &CliArgs, defs: &mut Definitions, i: usize) {
+    match defs.parse(line) {
+        // `f(x) = x^2 + 1` defines `f` for the following lines
+        Ok(ast) if defs.define_eq(&ast) => println!("{ast:#}"),
         Ok(ast) => {
-            let simplified = Simplifier::run(&ast);
+            let expanded = match defs.expand(&ast) {
+                Ok(expanded) => expanded,
+                Err(err) => {
+                    eprintln!("{err}");
+                    return;
+                }
+            };
+            if let Err(err) = expanded.reduce_matrices() {
+                eprintln!("{err}");
+                return;
+            }
+            let simplified = Simplifier::run(&expanded);
+
+            if cli.dot {
+                println!("{}\n{}", ast.to_dot(), simplified.to_dot());
+            }
 
             let style = if cli.latex_out {
                 PrettyStyle::LaTeX
@@ -70,7 +86,11 @@ fn run_line(line: &str, cli: &CliArgs, i: usize) {
 
             if cli.debug {
                 println!("dbg: {ast:?} = {simplified:?}");
-                println!("dbg: => {ast} = {simplified}");
+                println!(
+                    "dbg: => {} = {}",
+                    ast.clone().with_raw(true),
+                    simplified.clone().with_raw(true)
+                );
             }
             if cli.verbose {
                 println!("out[{i}]: {simplified}\n");